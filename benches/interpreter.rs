@@ -0,0 +1,34 @@
+//! Times both backends on loop- and call-heavy scripts. Run with
+//! `cargo bench`; each script is run a few times and the best time is kept.
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use lox_rs_ast::{run_file, Backend, Options};
+
+const SCRIPTS: &[&str] = &["fib", "loops", "closures"];
+const RUNS: u32 = 5;
+
+fn main() {
+    let scripts = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("benches/scripts");
+    for backend in [Backend::Tree, Backend::Vm] {
+        let options = Options {
+            backend,
+            ..Options::default()
+        };
+        for name in SCRIPTS {
+            let path = scripts.join(format!("{}.lox", name));
+            let mut best = Duration::MAX;
+            for _ in 0..RUNS {
+                let start = Instant::now();
+                run_file(path.clone(), options).unwrap();
+                best = best.min(start.elapsed());
+            }
+            eprintln!(
+                "{:>10} ({:?}): {:?} (best of {})",
+                name, backend, best, RUNS
+            );
+        }
+    }
+}
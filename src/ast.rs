@@ -1,134 +1,357 @@
-use std::hint::unreachable_unchecked;
+use std::{cell::Cell, hint::unreachable_unchecked, rc::Rc};
 
-use crate::tokens::Token;
+use crate::tokens::{Span, Token, TokenType};
+/// Where a variable lives, as worked out by the resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    /// `depth` scopes out from the innermost one, in the `slot`th variable
+    /// declared there.
+    Local { depth: usize, slot: usize },
+    /// The global with this index in the interpreter's globals table.
+    Global(usize),
+}
 #[derive(Debug)]
 pub enum Expr {
     Assign {
         name: Token,
         value: Box<Expr>,
+        binding: Cell<Option<Binding>>,
+        span: Span,
     },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
+        span: Span,
+    },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+        span: Span,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+        span: Span,
     },
     Grouping {
         expression: Box<Expr>,
+        span: Span,
     },
     Literal {
         value: Token,
+        span: Span,
     },
     Logical {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
+        span: Span,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+        span: Span,
+    },
+    Super {
+        keyword: Token,
+        method: Token,
+        binding: Cell<Option<Binding>>,
+        span: Span,
+    },
+    This {
+        keyword: Token,
+        binding: Cell<Option<Binding>>,
+        span: Span,
     },
     Unary {
         operator: Token,
         right: Box<Expr>,
+        span: Span,
     },
     Var {
         name: Token,
+        binding: Cell<Option<Binding>>,
+        span: Span,
     },
 }
 #[derive(Debug)]
 pub enum Stmt {
+    Class {
+        name: Token,
+        superclass: Option<Expr>,
+        methods: Vec<Stmt>,
+        span: Span,
+    },
     Expression {
         expression: Expr,
+        span: Span,
+    },
+    Function {
+        name: Token,
+        params: Vec<Token>,
+        body: Rc<Vec<Stmt>>,
+        span: Span,
     },
     If {
         condition: Expr,
         then_branch: Box<Stmt>,
         else_branch: Option<Box<Stmt>>,
+        span: Span,
     },
     Print {
         expression: Expr,
+        span: Span,
+    },
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+        span: Span,
     },
     Var {
         name: Token,
         initializer: Expr,
+        span: Span,
     },
     While {
         condition: Expr,
         body: Box<Stmt>,
+        span: Span,
     },
     Block {
         statements: Vec<Stmt>,
+        span: Span,
     },
 }
 pub trait ExprVisitor<T> {
     fn visit_binary_expr(&mut self, expr: &Expr) -> T;
+    fn visit_call_expr(&mut self, expr: &Expr) -> T;
     fn visit_grouping_expr(&mut self, expr: &Expr) -> T;
     fn visit_literal_expr(&mut self, expr: &Expr) -> T;
     fn visit_unary_expr(&mut self, expr: &Expr) -> T;
     fn visit_var_expr(&mut self, expr: &Expr) -> T;
     fn visit_assign_expr(&mut self, expr: &Expr) -> T;
     fn visit_logical_expr(&mut self, expr: &Expr) -> T;
+    fn visit_get_expr(&mut self, expr: &Expr) -> T;
+    fn visit_set_expr(&mut self, expr: &Expr) -> T;
+    fn visit_super_expr(&mut self, expr: &Expr) -> T;
+    fn visit_this_expr(&mut self, expr: &Expr) -> T;
 }
 pub trait StmtVisitor<T> {
     fn visit_print_stmt(&mut self, stmt: &Stmt) -> T;
+    fn visit_return_stmt(&mut self, stmt: &Stmt) -> T;
+    fn visit_function_stmt(&mut self, stmt: &Stmt) -> T;
     fn visit_if_stmt(&mut self, stmt: &Stmt) -> T;
     fn visit_expr_stmt(&mut self, stmt: &Stmt) -> T;
     fn visit_var_stmt(&mut self, stmt: &Stmt) -> T;
     fn visit_block_stmt(&mut self, stmt: &Stmt) -> T;
     fn visit_while_stmt(&mut self, stmt: &Stmt) -> T;
+    fn visit_class_stmt(&mut self, stmt: &Stmt) -> T;
 }
 impl Expr {
+    /// The source range the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Assign { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Get { span, .. }
+            | Expr::Grouping { span, .. }
+            | Expr::Literal { span, .. }
+            | Expr::Logical { span, .. }
+            | Expr::Set { span, .. }
+            | Expr::Super { span, .. }
+            | Expr::This { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Var { span, .. } => *span,
+        }
+    }
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Expr::Assign { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Get { span, .. }
+            | Expr::Grouping { span, .. }
+            | Expr::Literal { span, .. }
+            | Expr::Logical { span, .. }
+            | Expr::Set { span, .. }
+            | Expr::Super { span, .. }
+            | Expr::This { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Var { span, .. } => span,
+        }
+    }
     pub fn accept<T>(&self, visitor: &mut dyn ExprVisitor<T>) -> T {
         match self {
             Expr::Binary {
                 left: _,
                 operator: _,
                 right: _,
+                span: _,
             } => visitor.visit_binary_expr(self),
-            Expr::Grouping { expression: _ } => visitor.visit_grouping_expr(self),
-            Expr::Literal { value: _ } => visitor.visit_literal_expr(self),
+            Expr::Call {
+                callee: _,
+                paren: _,
+                arguments: _,
+                span: _,
+            } => visitor.visit_call_expr(self),
+            Expr::Grouping {
+                expression: _,
+                span: _,
+            } => visitor.visit_grouping_expr(self),
+            Expr::Literal { value: _, span: _ } => visitor.visit_literal_expr(self),
             Expr::Logical {
                 left: _,
                 operator: _,
                 right: _,
+                span: _,
             } => visitor.visit_logical_expr(self),
             Expr::Unary {
                 operator: _,
                 right: _,
+                span: _,
             } => visitor.visit_unary_expr(self),
-            Expr::Var { name: _ } => visitor.visit_var_expr(self),
-            Expr::Assign { name: _, value: _ } => visitor.visit_assign_expr(self),
+            Expr::Var {
+                name: _,
+                binding: _,
+                span: _,
+            } => visitor.visit_var_expr(self),
+            Expr::Assign {
+                name: _,
+                value: _,
+                binding: _,
+                span: _,
+            } => visitor.visit_assign_expr(self),
+            Expr::Get {
+                object: _,
+                name: _,
+                span: _,
+            } => visitor.visit_get_expr(self),
+            Expr::Set {
+                object: _,
+                name: _,
+                value: _,
+                span: _,
+            } => visitor.visit_set_expr(self),
+            Expr::Super {
+                keyword: _,
+                method: _,
+                binding: _,
+                span: _,
+            } => visitor.visit_super_expr(self),
+            Expr::This {
+                keyword: _,
+                binding: _,
+                span: _,
+            } => visitor.visit_this_expr(self),
         }
     }
 }
 impl Stmt {
+    /// The source range the statement was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Class { span, .. }
+            | Stmt::Expression { span, .. }
+            | Stmt::Function { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::Print { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::Var { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::Block { span, .. } => *span,
+        }
+    }
     pub fn accept<T>(&self, visitor: &mut dyn StmtVisitor<T>) -> T {
         match self {
-            Stmt::Expression { expression: _ } => visitor.visit_expr_stmt(self),
-            Stmt::Print { expression: _ } => visitor.visit_print_stmt(self),
+            Stmt::Expression {
+                expression: _,
+                span: _,
+            } => visitor.visit_expr_stmt(self),
+            Stmt::Print {
+                expression: _,
+                span: _,
+            } => visitor.visit_print_stmt(self),
+            Stmt::Return {
+                keyword: _,
+                value: _,
+                span: _,
+            } => visitor.visit_return_stmt(self),
+            Stmt::Function {
+                name: _,
+                params: _,
+                body: _,
+                span: _,
+            } => visitor.visit_function_stmt(self),
             Stmt::Var {
                 name: _,
                 initializer: _,
+                span: _,
             } => visitor.visit_var_stmt(self),
-            Stmt::Block { statements: _ } => visitor.visit_block_stmt(self),
+            Stmt::Block {
+                statements: _,
+                span: _,
+            } => visitor.visit_block_stmt(self),
             Stmt::If {
                 condition: _,
                 then_branch: _,
                 else_branch: _,
+                span: _,
             } => visitor.visit_if_stmt(self),
             Stmt::While {
                 condition: _,
                 body: _,
+                span: _,
             } => visitor.visit_while_stmt(self),
+            Stmt::Class {
+                name: _,
+                superclass: _,
+                methods: _,
+                span: _,
+            } => visitor.visit_class_stmt(self),
         }
     }
 }
+/// Prints syntax trees as S-expressions, e.g. `(var x (+ 1 2))`, one
+/// top-level statement per line.
+#[derive(Default)]
 pub struct AstPrinter {}
 impl AstPrinter {
     pub fn print(&mut self, expr: &Expr) -> String {
         expr.accept(self)
     }
+    pub fn print_stmt(&mut self, stmt: &Stmt) -> String {
+        stmt.accept(self)
+    }
+    pub fn print_program(&mut self, statements: &[Stmt]) -> String {
+        statements
+            .iter()
+            .map(|stmt| self.print_stmt(stmt) + "\n")
+            .collect()
+    }
+    /// `(head part...)`, leaving out empty parts.
+    fn parenthesize(&mut self, head: &str, parts: &[String]) -> String {
+        let mut out = format!("({}", head);
+        for part in parts.iter().filter(|part| !part.is_empty()) {
+            out.push(' ');
+            out.push_str(part);
+        }
+        out.push(')');
+        out
+    }
 }
 impl ExprVisitor<String> for AstPrinter {
     fn visit_unary_expr(&mut self, expr: &Expr) -> String {
         match expr {
-            Expr::Unary { operator, right } => format!("({:?}{})", operator, self.print(right)),
+            Expr::Unary {
+                operator, right, ..
+            } => {
+                let right = self.print(right);
+                self.parenthesize(&operator.lexeme(), &[right])
+            }
             _ => unsafe { unreachable_unchecked() },
         }
     }
@@ -138,37 +361,59 @@ impl ExprVisitor<String> for AstPrinter {
                 left,
                 operator,
                 right,
-            } => format!(
-                "({} {:?} {})",
-                self.print(left),
-                operator,
-                self.print(right)
-            ),
+                ..
+            } => {
+                let parts = [self.print(left), self.print(right)];
+                self.parenthesize(&operator.lexeme(), &parts)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_call_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                let parts: Vec<_> = std::iter::once(callee.as_ref())
+                    .chain(arguments)
+                    .map(|expr| self.print(expr))
+                    .collect();
+                self.parenthesize("call", &parts)
+            }
             _ => unsafe { unreachable_unchecked() },
         }
     }
     fn visit_grouping_expr(&mut self, expr: &Expr) -> String {
         match expr {
-            Expr::Grouping { expression } => format!("(group {})", self.print(expression)),
+            Expr::Grouping { expression, .. } => {
+                let expression = self.print(expression);
+                self.parenthesize("group", &[expression])
+            }
             _ => unsafe { unreachable_unchecked() },
         }
     }
     fn visit_literal_expr(&mut self, expr: &Expr) -> String {
         match expr {
-            Expr::Literal { value } => format!("{:?}", value),
+            Expr::Literal { value, .. } => match &value.token_type {
+                TokenType::String(s) => format!("{:?}", s),
+                other => other.to_string(),
+            },
             _ => unsafe { unreachable_unchecked() },
         }
     }
     fn visit_var_expr(&mut self, expr: &Expr) -> String {
         match expr {
-            Expr::Var { name } => format!("{:?}", name),
+            Expr::Var { name, .. } => name.lexeme(),
             _ => unsafe { unreachable_unchecked() },
         }
     }
 
     fn visit_assign_expr(&mut self, expr: &Expr) -> String {
         match expr {
-            Expr::Assign { name, value } => format!("{:?}={:?}", name, value),
+            Expr::Assign { name, value, .. } => {
+                let value = self.print(value);
+                self.parenthesize("=", &[name.lexeme(), value])
+            }
             _ => unsafe { unreachable_unchecked() },
         }
     }
@@ -179,12 +424,163 @@ impl ExprVisitor<String> for AstPrinter {
                 left,
                 operator,
                 right,
-            } => format!(
-                "({} {:?} {})",
-                self.print(left),
-                operator,
-                self.print(right)
-            ),
+                ..
+            } => {
+                let parts = [self.print(left), self.print(right)];
+                self.parenthesize(&operator.lexeme(), &parts)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+
+    fn visit_get_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Get { object, name, .. } => {
+                let object = self.print(object);
+                self.parenthesize(".", &[object, name.lexeme()])
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+
+    fn visit_set_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                let object = self.print(object);
+                let target = self.parenthesize(".", &[object, name.lexeme()]);
+                let value = self.print(value);
+                self.parenthesize("=", &[target, value])
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+
+    fn visit_super_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Super { method, .. } => self.parenthesize("super", &[method.lexeme()]),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+
+    fn visit_this_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::This { .. } => "this".to_string(),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_print_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Print { expression, .. } => {
+                let expression = self.print(expression);
+                self.parenthesize("print", &[expression])
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_return_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Return { value, .. } => {
+                let value = value.as_ref().map_or(String::new(), |v| self.print(v));
+                self.parenthesize("return", &[value])
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_function_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Function {
+                name, params, body, ..
+            } => {
+                let params: Vec<_> = params.iter().map(Token::lexeme).collect();
+                let mut parts = vec![name.lexeme(), format!("({})", params.join(" "))];
+                parts.extend(body.iter().map(|stmt| self.print_stmt(stmt)));
+                self.parenthesize("fun", &parts)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_if_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let parts = [
+                    self.print(condition),
+                    self.print_stmt(then_branch),
+                    else_branch
+                        .as_ref()
+                        .map_or(String::new(), |branch| self.print_stmt(branch)),
+                ];
+                self.parenthesize("if", &parts)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_expr_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Expression { expression, .. } => {
+                let expression = self.print(expression);
+                self.parenthesize(";", &[expression])
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                let initializer = self.print(initializer);
+                self.parenthesize("var", &[name.lexeme(), initializer])
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_block_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                let parts: Vec<_> = statements.iter().map(|s| self.print_stmt(s)).collect();
+                self.parenthesize("block", &parts)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_while_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::While {
+                condition, body, ..
+            } => {
+                let parts = [self.print(condition), self.print_stmt(body)];
+                self.parenthesize("while", &parts)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_class_stmt(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let mut parts = vec![name.lexeme()];
+                if let Some(superclass) = superclass {
+                    parts.push(format!("< {}", self.print(superclass)));
+                }
+                parts.extend(methods.iter().map(|method| self.print_stmt(method)));
+                self.parenthesize("class", &parts)
+            }
             _ => unsafe { unreachable_unchecked() },
         }
     }
@@ -0,0 +1,301 @@
+use std::{fmt::Write, rc::Rc};
+
+use crate::{tokens::Span, tokens::Token, value::Value};
+
+macro_rules! opcodes {
+    ($($(#[$doc:meta])* $name:ident,)*) => {
+        /// A VM instruction. Operands follow the opcode in the chunk and are
+        /// big-endian `u16`s unless noted otherwise.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum OpCode {
+            $($(#[$doc])* $name,)*
+        }
+        impl OpCode {
+            const ALL: &'static [OpCode] = &[$(OpCode::$name,)*];
+            pub fn from_byte(byte: u8) -> Option<OpCode> {
+                OpCode::ALL.get(byte as usize).copied()
+            }
+        }
+    };
+}
+opcodes! {
+    /// `index`: pushes a constant.
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// `depth`, `slot`: pushes a local variable.
+    GetLocal,
+    /// `depth`, `slot`: stores the top of the stack in a local variable.
+    SetLocal,
+    /// Pops a value into the next slot of the innermost scope.
+    DefineLocal,
+    /// `index`: pushes a global variable.
+    GetGlobal,
+    /// `index`: stores the top of the stack in a global variable.
+    SetGlobal,
+    /// `index`: pops a value into a global variable.
+    DefineGlobal,
+    /// `name`: replaces an instance with one of its properties.
+    GetProperty,
+    /// `name`: pops a value and an instance, sets the field and pushes the
+    /// value back.
+    SetProperty,
+    /// `name`: pops a superclass and an instance and pushes the superclass'
+    /// method bound to the instance.
+    GetSuper,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// `offset`: jumps forward.
+    Jump,
+    /// `offset`: jumps forward if the top of the stack is falsey, leaving it
+    /// in place.
+    JumpIfFalse,
+    /// `offset`: jumps backward.
+    Loop,
+    /// `count` (`u8`): calls the value below the top `count` arguments.
+    Call,
+    /// `function`: pushes a closure over the current environment.
+    Closure,
+    /// `name`, `has_superclass` (`u8`), `count` (`u8`), then `count`
+    /// `function`s: pops the superclass if there is one and pushes a class
+    /// with those methods.
+    Class,
+    EnterScope,
+    ExitScope,
+    Return,
+}
+
+/// A function compiled ahead of time, from which closures are made at
+/// runtime.
+#[derive(Debug)]
+pub struct Prototype {
+    pub name: Token,
+    pub arity: usize,
+    pub chunk: Rc<Chunk>,
+}
+
+/// A compiled sequence of instructions with the constants they refer to.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    pub functions: Vec<Prototype>,
+    /// Where each instruction came from, run-length encoded: the span of an
+    /// instruction is that of the last entry at or before its offset.
+    spans: Vec<(usize, Span)>,
+    /// The spans of both operands of instructions whose errors may blame
+    /// either one, sorted by offset.
+    operand_spans: Vec<(usize, Span, Span)>,
+    /// The names of the variables local instructions refer to, sorted by
+    /// offset, since their operands only locate the slot.
+    local_names: Vec<(usize, Rc<str>)>,
+}
+impl Chunk {
+    /// Appends `op`, returning its offset.
+    pub fn write_op(&mut self, op: OpCode, span: Span) -> usize {
+        let offset = self.code.len();
+        if self.spans.last().map(|(_, last)| *last) != Some(span) {
+            self.spans.push((offset, span));
+        }
+        self.code.push(op as u8);
+        offset
+    }
+    pub fn write_u8(&mut self, byte: u8) {
+        self.code.push(byte);
+    }
+    pub fn write_u16(&mut self, value: u16) {
+        self.code.extend(value.to_be_bytes());
+    }
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+    pub fn patch_u16(&mut self, offset: usize, value: u16) {
+        self.code[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+    /// Adds a constant, returning its index, or `None` if the pool is full.
+    pub fn add_constant(&mut self, value: Value) -> Option<u16> {
+        self.constants.push(value);
+        u16::try_from(self.constants.len() - 1).ok()
+    }
+    pub fn add_function(&mut self, function: Prototype) -> Option<u16> {
+        self.functions.push(function);
+        u16::try_from(self.functions.len() - 1).ok()
+    }
+    /// The span of the instruction at `offset`.
+    pub fn span_at(&self, offset: usize) -> Span {
+        let index = self.spans.partition_point(|(start, _)| *start <= offset);
+        self.spans[index.saturating_sub(1)].1
+    }
+    /// Records the operand spans of the instruction just written at
+    /// `offset`. Instructions are written in order, which keeps the table
+    /// sorted.
+    pub fn set_operand_spans(&mut self, offset: usize, first: Span, second: Span) {
+        debug_assert!(self
+            .operand_spans
+            .last()
+            .is_none_or(|(at, ..)| *at < offset));
+        self.operand_spans.push((offset, first, second));
+    }
+    /// The operand spans of the instruction at `offset`, or its own span
+    /// twice if none were recorded. Only needed to report an error.
+    pub fn operand_spans(&self, offset: usize) -> (Span, Span) {
+        let index = self.operand_spans.partition_point(|(at, ..)| *at < offset);
+        match self.operand_spans.get(index) {
+            Some((at, first, second)) if *at == offset => (*first, *second),
+            _ => (self.span_at(offset), self.span_at(offset)),
+        }
+    }
+    /// Records the name of the variable the local instruction just written
+    /// at `offset` refers to.
+    pub fn set_local_name(&mut self, offset: usize, name: &str) {
+        debug_assert!(self.local_names.last().is_none_or(|(at, _)| *at < offset));
+        self.local_names.push((offset, name.into()));
+    }
+    /// The name of the variable the local instruction at `offset` refers to.
+    pub fn local_name(&self, offset: usize) -> &str {
+        let index = self.local_names.partition_point(|(at, _)| *at < offset);
+        match self.local_names.get(index) {
+            Some((at, name)) if *at == offset => name,
+            _ => "",
+        }
+    }
+    /// A listing of the chunk, one instruction per line, followed by the
+    /// chunks of the functions it defines.
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
+        let mut offset = 0;
+        while offset < self.code.len() {
+            offset = self.disassemble_instruction(&mut out, offset);
+        }
+        for function in &self.functions {
+            out.push_str(&function.chunk.disassemble(&function.name.lexeme()));
+        }
+        out
+    }
+    fn disassemble_instruction(&self, out: &mut String, offset: usize) -> usize {
+        let line = self.span_at(offset).line;
+        let Some(op) = OpCode::from_byte(self.code[offset]) else {
+            writeln!(
+                out,
+                "{:04} {:>4} <bad opcode {}>",
+                offset, line, self.code[offset]
+            )
+            .unwrap();
+            return offset + 1;
+        };
+        write!(out, "{:04} {:>4} {:?}", offset, line, op).unwrap();
+        let operand = |i: usize| self.read_u16(offset + 1 + 2 * i);
+        let next = match op {
+            OpCode::Constant => {
+                write!(
+                    out,
+                    " {} ({})",
+                    operand(0),
+                    self.constants[operand(0) as usize]
+                )
+                .unwrap();
+                offset + 3
+            }
+            OpCode::GetProperty | OpCode::SetProperty | OpCode::GetSuper => {
+                write!(out, " {}", self.constants[operand(0) as usize]).unwrap();
+                offset + 3
+            }
+            OpCode::GetGlobal | OpCode::SetGlobal | OpCode::DefineGlobal => {
+                write!(out, " {}", operand(0)).unwrap();
+                offset + 3
+            }
+            OpCode::GetLocal | OpCode::SetLocal => {
+                write!(out, " {} {}", operand(0), operand(1)).unwrap();
+                offset + 5
+            }
+            OpCode::Jump | OpCode::JumpIfFalse => {
+                write!(out, " -> {:04}", offset + 3 + operand(0) as usize).unwrap();
+                offset + 3
+            }
+            OpCode::Loop => {
+                write!(out, " -> {:04}", offset + 3 - operand(0) as usize).unwrap();
+                offset + 3
+            }
+            OpCode::Call => {
+                write!(out, " {}", self.code[offset + 1]).unwrap();
+                offset + 2
+            }
+            OpCode::Closure => {
+                let function = &self.functions[operand(0) as usize];
+                write!(out, " <fn {}>", function.name.lexeme()).unwrap();
+                offset + 3
+            }
+            OpCode::Class => {
+                let count = self.code[offset + 4] as usize;
+                write!(out, " {}", self.constants[operand(0) as usize]).unwrap();
+                if self.code[offset + 3] != 0 {
+                    write!(out, " <").unwrap();
+                }
+                for i in 0..count {
+                    let function = self.read_u16(offset + 5 + 2 * i);
+                    write!(out, " {}", self.functions[function as usize].name.lexeme()).unwrap();
+                }
+                offset + 5 + 2 * count
+            }
+            _ => offset + 1,
+        };
+        out.push('\n');
+        next
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn spans_are_run_length_encoded() {
+        let mut chunk = Chunk::default();
+        let first = Span::new(0, 1, 1, 1);
+        let second = Span::new(2, 3, 2, 1);
+        chunk.write_op(OpCode::Nil, first);
+        chunk.write_op(OpCode::Nil, first);
+        let jump = chunk.write_op(OpCode::Jump, second);
+        chunk.write_u16(0);
+        chunk.write_op(OpCode::Return, second);
+        assert_eq!(chunk.spans.len(), 2);
+        assert_eq!(chunk.span_at(1), first);
+        assert_eq!(chunk.span_at(jump + 1), second);
+        assert_eq!(chunk.span_at(5), second);
+    }
+    #[test]
+    fn operand_spans_and_local_names_are_found_by_offset() {
+        let mut chunk = Chunk::default();
+        let own = Span::new(4, 5, 1, 5);
+        let (left, right) = (Span::new(0, 1, 1, 1), Span::new(8, 9, 1, 9));
+        for offset in 0..4 {
+            chunk.write_op(OpCode::Add, own);
+            if offset % 2 == 1 {
+                chunk.set_operand_spans(offset, left, right);
+                chunk.set_local_name(offset, &format!("v{}", offset));
+            }
+        }
+        assert_eq!(chunk.operand_spans(3), (left, right));
+        assert_eq!(chunk.operand_spans(2), (own, own));
+        assert_eq!((chunk.local_name(1), chunk.local_name(2)), ("v1", ""));
+    }
+    #[test]
+    fn u16_operands_are_big_endian() {
+        let mut chunk = Chunk::default();
+        chunk.write_u16(0x1234);
+        assert_eq!(chunk.code, vec![0x12, 0x34]);
+        chunk.patch_u16(0, 0xabcd);
+        assert_eq!(chunk.read_u16(0), 0xabcd);
+    }
+}
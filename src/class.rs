@@ -0,0 +1,94 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
+
+use crate::{
+    function::{LoxCallable, LoxFunction},
+    interpreter::{Interpreter, InterpreterError},
+    tokens::Span,
+    value::Value,
+};
+
+pub struct LoxClass {
+    name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<String, Rc<LoxFunction>>,
+}
+impl LoxClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Rc<LoxFunction>>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods,
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Looks `name` up on this class, then along the superclass chain.
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+// Instances need a handle on their class, so calling is implemented for the
+// shared pointer rather than the class itself.
+impl LoxCallable for Rc<LoxClass> {
+    fn arity(&self) -> usize {
+        self.find_method("init")
+            .map_or(0, |initializer| initializer.arity())
+    }
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, InterpreterError> {
+        let instance = Value::Instance(Rc::new(RefCell::new(LoxInstance::new(self.clone()))));
+        if let Some(initializer) = self.find_method("init") {
+            initializer
+                .bind(instance.clone())
+                .call(interpreter, arguments, span)?;
+        }
+        Ok(instance)
+    }
+}
+impl Debug for LoxClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: HashMap<String, Value>,
+}
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+    pub fn class(&self) -> &Rc<LoxClass> {
+        &self.class
+    }
+    pub fn get_field(&self, name: &str) -> Option<Value> {
+        self.fields.get(name).cloned()
+    }
+    pub fn set_field(&mut self, name: String, value: Value) {
+        self.fields.insert(name, value);
+    }
+}
+impl Debug for LoxInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name())
+    }
+}
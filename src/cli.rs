@@ -4,6 +4,10 @@ use std::path::PathBuf;
 //
 use clap::{Parser, Subcommand};
 use clap_verbosity_flag::Verbosity;
+
+use crate::{
+    diagnostics::ColorChoice, dump::DumpFormat, linter::Lint, scanner::Dialect, Backend, Options,
+};
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
@@ -12,11 +16,71 @@ pub struct Cli {
     // Verbosity flag
     #[command(flatten)]
     pub verbose: Verbosity,
+    /// Language dialect to accept
+    #[arg(long, value_enum, global = true, default_value_t = Dialect::Lox)]
+    pub dialect: Dialect,
+    /// When to colour error messages
+    #[arg(long, value_enum, global = true, default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+    /// Engine to run the code with
+    #[arg(long, value_enum, global = true, default_value_t = Backend::Tree)]
+    pub backend: Backend,
+    /// Fold constants and drop dead branches before running
+    #[arg(short = 'O', long, global = true)]
+    pub optimize: bool,
+}
+impl Cli {
+    pub fn options(&self) -> Options {
+        Options {
+            dialect: self.dialect,
+            color: self.color,
+            backend: self.backend,
+            optimize: self.optimize,
+        }
+    }
 }
 #[derive(Subcommand)]
 pub enum Commands {
+    #[command(visible_alias = "run")]
     File {
         /// file to to interpret. A positional argument
         file: PathBuf,
+        /// Start the REPL once the file has run, with its globals defined
+        #[arg(short, long)]
+        interactive: bool,
+    },
+    /// Print the tokens of a file
+    Tokens {
+        file: PathBuf,
+        /// How to print them
+        #[arg(long, value_enum, default_value_t = DumpFormat::Tree)]
+        format: DumpFormat,
+    },
+    /// Print the syntax tree of a file
+    Ast {
+        file: PathBuf,
+        /// How to print it
+        #[arg(long, value_enum, default_value_t = DumpFormat::Tree)]
+        format: DumpFormat,
+    },
+    /// Reformat files, printing the result
+    Fmt {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Only report files that are not formatted
+        #[arg(long, conflicts_with = "write")]
+        check: bool,
+        /// Rewrite the files in place
+        #[arg(short, long)]
+        write: bool,
+    },
+    /// Report likely mistakes without running the code, failing if any are
+    /// found
+    Check {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Lint to leave out, by name or code. Can be repeated
+        #[arg(short = 'A', long, value_enum)]
+        allow: Vec<Lint>,
     },
 }
@@ -0,0 +1,636 @@
+use std::{hint::unreachable_unchecked, rc::Rc};
+
+use thiserror::Error;
+
+use crate::{
+    ast::{Binding, Expr, ExprVisitor, Stmt, StmtVisitor},
+    chunk::{Chunk, OpCode, Prototype},
+    environment::Globals,
+    tokens::{Span, Token, TokenType},
+    value::Value,
+};
+#[derive(Error, Debug)]
+pub enum CompileError {
+    #[error("Too many constants in one chunk at {0}")]
+    TooManyConstants(Span),
+    #[error("Too much code to jump over at {0}")]
+    JumpTooLarge(Span),
+    #[error("Too many local variables in scope at {0}")]
+    TooManyLocals(Span),
+    #[error("Too many methods in one class at {0}")]
+    TooManyMethods(Span),
+}
+
+/// Compiles resolved statements into a [`Chunk`] for the [`Vm`](crate::vm::Vm).
+///
+/// Variables live in the same slot-indexed scopes the tree-walker uses, so
+/// the compiler opens a scope wherever the interpreter would and addresses
+/// locals by the depth and slot the resolver assigned.
+pub struct Compiler<'a> {
+    chunk: Chunk,
+    globals: &'a mut Globals,
+    /// How many scopes are open in the function being compiled. Declarations
+    /// at depth 0 of the script are globals.
+    depth: usize,
+    error: Option<CompileError>,
+}
+impl<'a> Compiler<'a> {
+    /// A compiler interning global names into `globals`, which must be the
+    /// table of the VM that will run the code.
+    pub fn new(globals: &'a mut Globals) -> Self {
+        Self {
+            chunk: Chunk::default(),
+            globals,
+            depth: 0,
+            error: None,
+        }
+    }
+    /// Compiles a script. The resulting chunk returns `nil` when it is done.
+    pub fn compile(mut self, statements: &[Stmt]) -> Result<Chunk, CompileError> {
+        for stmt in statements {
+            stmt.accept(&mut self);
+        }
+        let end = statements.last().map_or_else(Span::default, Stmt::span);
+        self.emit(OpCode::Nil, end);
+        self.emit(OpCode::Return, end);
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.chunk),
+        }
+    }
+    /// Compiles a function body in a compiler of its own. The parameters
+    /// occupy the scope the VM opens for the call.
+    fn function(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> Prototype {
+        let mut compiler = Compiler::new(self.globals);
+        compiler.depth = 1;
+        for stmt in body {
+            stmt.accept(&mut compiler);
+        }
+        compiler.emit(OpCode::Nil, name.span);
+        compiler.emit(OpCode::Return, name.span);
+        if let Some(e) = compiler.error {
+            self.error.get_or_insert(e);
+        }
+        Prototype {
+            name: name.clone(),
+            arity: params.len(),
+            chunk: Rc::new(compiler.chunk),
+        }
+    }
+    fn emit(&mut self, op: OpCode, span: Span) -> usize {
+        self.chunk.write_op(op, span)
+    }
+    fn emit_with(&mut self, op: OpCode, operand: u16, span: Span) {
+        self.emit(op, span);
+        self.chunk.write_u16(operand);
+    }
+    fn constant(&mut self, value: Value, span: Span) -> u16 {
+        match self.chunk.add_constant(value) {
+            Some(index) => index,
+            None => {
+                self.error
+                    .get_or_insert(CompileError::TooManyConstants(span));
+                0
+            }
+        }
+    }
+    fn add_function(&mut self, function: Prototype, span: Span) -> u16 {
+        match self.chunk.add_function(function) {
+            Some(index) => index,
+            None => {
+                self.error
+                    .get_or_insert(CompileError::TooManyConstants(span));
+                0
+            }
+        }
+    }
+    fn name_constant(&mut self, name: &Token) -> u16 {
+        self.constant(Value::from(name.lexeme().as_str()), name.span)
+    }
+    fn global(&mut self, name: &Token) -> u16 {
+        let index = self.globals.intern(&name.lexeme());
+        self.global_index(index, name.span)
+    }
+    fn global_index(&mut self, index: usize, span: Span) -> u16 {
+        u16::try_from(index).unwrap_or_else(|_| {
+            self.error
+                .get_or_insert(CompileError::TooManyConstants(span));
+            0
+        })
+    }
+    /// Emits a forward jump to be patched once its target is known.
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit_with(op, u16::MAX, span);
+        self.chunk.code.len() - 2
+    }
+    fn patch_jump(&mut self, operand: usize, span: Span) {
+        let distance = self.chunk.code.len() - operand - 2;
+        let distance = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error.get_or_insert(CompileError::JumpTooLarge(span));
+            0
+        });
+        self.chunk.patch_u16(operand, distance);
+    }
+    fn emit_loop(&mut self, start: usize, span: Span) {
+        let distance = self.chunk.code.len() + 3 - start;
+        let distance = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error.get_or_insert(CompileError::JumpTooLarge(span));
+            0
+        });
+        self.emit_with(OpCode::Loop, distance, span);
+    }
+    /// Stores the value on top of the stack in a new variable called `name`.
+    fn define(&mut self, name: &Token) {
+        if self.depth == 0 {
+            let index = self.global(name);
+            self.emit_with(OpCode::DefineGlobal, index, name.span);
+        } else {
+            self.emit(OpCode::DefineLocal, name.span);
+        }
+    }
+    fn get_variable(&mut self, name: &Token, binding: Option<Binding>) {
+        match binding {
+            Some(Binding::Local { depth, slot }) => self.local(OpCode::GetLocal, depth, slot, name),
+            Some(Binding::Global(index)) => {
+                let index = self.global_index(index, name.span);
+                self.emit_with(OpCode::GetGlobal, index, name.span);
+            }
+            None => {
+                let index = self.global(name);
+                self.emit_with(OpCode::GetGlobal, index, name.span);
+            }
+        }
+    }
+    fn local(&mut self, op: OpCode, depth: usize, slot: usize, name: &Token) {
+        let (depth, slot) = match (u16::try_from(depth), u16::try_from(slot)) {
+            (Ok(depth), Ok(slot)) => (depth, slot),
+            _ => {
+                self.error
+                    .get_or_insert(CompileError::TooManyLocals(name.span));
+                (0, 0)
+            }
+        };
+        let offset = self.emit(op, name.span);
+        self.chunk.set_local_name(offset, &name.lexeme());
+        self.chunk.write_u16(depth);
+        self.chunk.write_u16(slot);
+    }
+    fn compile_block(&mut self, statements: &[Stmt], span: Span) {
+        self.emit(OpCode::EnterScope, span);
+        self.depth += 1;
+        for stmt in statements {
+            stmt.accept(self);
+        }
+        self.depth -= 1;
+        self.emit(OpCode::ExitScope, span);
+    }
+}
+impl StmtVisitor<()> for Compiler<'_> {
+    fn visit_print_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print { expression, span } => {
+                expression.accept(self);
+                self.emit(OpCode::Print, *span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_return_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Return { keyword, value, .. } => {
+                match value {
+                    Some(value) => value.accept(self),
+                    None => {
+                        self.emit(OpCode::Nil, keyword.span);
+                    }
+                }
+                self.emit(OpCode::Return, keyword.span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_function_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Function {
+                name, params, body, ..
+            } => {
+                let function = self.function(name, params, body);
+                let index = self.add_function(function, name.span);
+                self.emit_with(OpCode::Closure, index, name.span);
+                self.define(name);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_if_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                span,
+            } => {
+                condition.accept(self);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse, *span);
+                self.emit(OpCode::Pop, *span);
+                then_branch.accept(self);
+                let else_jump = self.emit_jump(OpCode::Jump, *span);
+                self.patch_jump(then_jump, *span);
+                self.emit(OpCode::Pop, *span);
+                if let Some(else_branch) = else_branch {
+                    else_branch.accept(self);
+                }
+                self.patch_jump(else_jump, *span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_expr_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression, span } => {
+                expression.accept(self);
+                self.emit(OpCode::Pop, *span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                initializer.accept(self);
+                self.define(name);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_block_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, span } => self.compile_block(statements, *span),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_while_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::While {
+                condition,
+                body,
+                span,
+            } => {
+                let start = self.chunk.code.len();
+                condition.accept(self);
+                let exit = self.emit_jump(OpCode::JumpIfFalse, *span);
+                self.emit(OpCode::Pop, *span);
+                body.accept(self);
+                self.emit_loop(start, *span);
+                self.patch_jump(exit, *span);
+                self.emit(OpCode::Pop, *span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_class_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                // Errors about the superclass point at it.
+                let span = match superclass {
+                    Some(superclass) => {
+                        superclass.accept(self);
+                        superclass.span()
+                    }
+                    None => name.span,
+                };
+                let methods: Vec<_> = methods
+                    .iter()
+                    .filter_map(|method| match method {
+                        Stmt::Function {
+                            name, params, body, ..
+                        } => {
+                            let function = self.function(name, params, body);
+                            Some(self.add_function(function, name.span))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let name_constant = self.name_constant(name);
+                self.emit_with(OpCode::Class, name_constant, span);
+                self.chunk.write_u8(superclass.is_some() as u8);
+                let count = u8::try_from(methods.len()).unwrap_or_else(|_| {
+                    self.error
+                        .get_or_insert(CompileError::TooManyMethods(name.span));
+                    0
+                });
+                self.chunk.write_u8(count);
+                for method in methods {
+                    self.chunk.write_u16(method);
+                }
+                self.define(name);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
+impl ExprVisitor<()> for Compiler<'_> {
+    fn visit_binary_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                left.accept(self);
+                right.accept(self);
+                let op = match operator.token_type {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::EqualEqual | TokenType::BangEqual => OpCode::Equal,
+                    _ => unsafe { unreachable_unchecked() },
+                };
+                let offset = self.emit(op, operator.span);
+                self.chunk
+                    .set_operand_spans(offset, left.span(), right.span());
+                if operator.token_type == TokenType::BangEqual {
+                    self.emit(OpCode::Not, operator.span);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_call_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call {
+                callee,
+                arguments,
+                span,
+                ..
+            } => {
+                callee.accept(self);
+                for argument in arguments {
+                    argument.accept(self);
+                }
+                let offset = self.emit(OpCode::Call, *span);
+                self.chunk.write_u8(arguments.len() as u8);
+                self.chunk.set_operand_spans(offset, callee.span(), *span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_grouping_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Grouping { expression, .. } => expression.accept(self),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_literal_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal { value, span } => match Value::from_literal(&value.token_type) {
+                Some(Value::Nil) => {
+                    self.emit(OpCode::Nil, *span);
+                }
+                Some(Value::Bool(true)) => {
+                    self.emit(OpCode::True, *span);
+                }
+                Some(Value::Bool(false)) => {
+                    self.emit(OpCode::False, *span);
+                }
+                Some(value) => {
+                    let index = self.constant(value, *span);
+                    self.emit_with(OpCode::Constant, index, *span);
+                }
+                None => unreachable!("literal expressions only hold literal tokens"),
+            },
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_unary_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Unary {
+                operator, right, ..
+            } => {
+                right.accept(self);
+                match operator.token_type {
+                    // A bad operand is blamed on the operand itself.
+                    TokenType::Minus => self.emit(OpCode::Negate, right.span()),
+                    TokenType::Bang => self.emit(OpCode::Not, operator.span),
+                    _ => unsafe { unreachable_unchecked() },
+                };
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Var { name, binding, .. } => self.get_variable(name, binding.get()),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_assign_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign {
+                name,
+                value,
+                binding,
+                ..
+            } => {
+                value.accept(self);
+                match binding.get() {
+                    Some(Binding::Local { depth, slot }) => {
+                        self.local(OpCode::SetLocal, depth, slot, name)
+                    }
+                    Some(Binding::Global(index)) => {
+                        let index = self.global_index(index, name.span);
+                        self.emit_with(OpCode::SetGlobal, index, name.span);
+                    }
+                    None => {
+                        let index = self.global(name);
+                        self.emit_with(OpCode::SetGlobal, index, name.span);
+                    }
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_logical_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                left.accept(self);
+                let span = operator.span;
+                if operator.token_type == TokenType::And {
+                    let end = self.emit_jump(OpCode::JumpIfFalse, span);
+                    self.emit(OpCode::Pop, span);
+                    right.accept(self);
+                    self.patch_jump(end, span);
+                } else {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                    let end = self.emit_jump(OpCode::Jump, span);
+                    self.patch_jump(else_jump, span);
+                    self.emit(OpCode::Pop, span);
+                    right.accept(self);
+                    self.patch_jump(end, span);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_get_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Get { object, name, .. } => {
+                object.accept(self);
+                let name_constant = self.name_constant(name);
+                self.emit_with(OpCode::GetProperty, name_constant, name.span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_set_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                object.accept(self);
+                value.accept(self);
+                let name_constant = self.name_constant(name);
+                self.emit_with(OpCode::SetProperty, name_constant, name.span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_super_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Super {
+                keyword,
+                method,
+                binding,
+                ..
+            } => {
+                // `super` sits alone in its scope, with `this` alone in the
+                // scope just inside it.
+                let depth = match binding.get() {
+                    Some(Binding::Local { depth, .. }) => depth,
+                    _ => 0,
+                };
+                self.local(OpCode::GetLocal, depth.saturating_sub(1), 0, keyword);
+                self.local(OpCode::GetLocal, depth, 0, keyword);
+                let name_constant = self.name_constant(method);
+                self.emit_with(OpCode::GetSuper, name_constant, method.span);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_this_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::This {
+                keyword, binding, ..
+            } => match binding.get() {
+                Some(Binding::Local { depth, slot }) => {
+                    self.local(OpCode::GetLocal, depth, slot, keyword)
+                }
+                _ => {
+                    self.emit(OpCode::Nil, keyword.span);
+                }
+            },
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::parse_source, resolver::Resolver, scanner::Dialect};
+    fn try_compile(source: &str) -> Result<Chunk, CompileError> {
+        let (stmts, _) = parse_source(source, Dialect::Lox);
+        let mut globals = Globals::default();
+        assert!(Resolver::new(&mut globals).resolve(&stmts).is_empty());
+        Compiler::new(&mut globals).compile(&stmts)
+    }
+    fn compile(source: &str) -> Chunk {
+        try_compile(source).unwrap()
+    }
+    #[test]
+    fn compiles_expressions_to_stack_code() {
+        let chunk = compile("var a = 1;\nprint -a + 2 * 3;");
+        assert_eq!(
+            chunk.disassemble("script"),
+            "== script ==\n\
+             0000    1 Constant 0 (1)\n\
+             0003    1 DefineGlobal 0\n\
+             0006    2 GetGlobal 0\n\
+             0009    2 Negate\n\
+             0010    2 Constant 1 (2)\n\
+             0013    2 Constant 2 (3)\n\
+             0016    2 Multiply\n\
+             0017    2 Add\n\
+             0018    2 Print\n\
+             0019    2 Nil\n\
+             0020    2 Return\n"
+        );
+    }
+    #[test]
+    fn compiles_control_flow_and_functions() {
+        let chunk = compile("fun f(n) { while (n > 0) n = n - 1; return n; }");
+        assert_eq!(
+            chunk.disassemble("script"),
+            "== script ==\n\
+             0000    1 Closure <fn f>\n\
+             0003    1 DefineGlobal 0\n\
+             0006    1 Nil\n\
+             0007    1 Return\n\
+             == f ==\n\
+             0000    1 GetLocal 0 0\n\
+             0005    1 Constant 0 (0)\n\
+             0008    1 Greater\n\
+             0009    1 JumpIfFalse -> 0031\n\
+             0012    1 Pop\n\
+             0013    1 GetLocal 0 0\n\
+             0018    1 Constant 1 (1)\n\
+             0021    1 Subtract\n\
+             0022    1 SetLocal 0 0\n\
+             0027    1 Pop\n\
+             0028    1 Loop -> 0000\n\
+             0031    1 Pop\n\
+             0032    1 GetLocal 0 0\n\
+             0037    1 Return\n\
+             0038    1 Nil\n\
+             0039    1 Return\n"
+        );
+    }
+    #[test]
+    fn too_many_methods() {
+        let methods: String = (0..256).map(|i| format!("m{}() {{}}\n", i)).collect();
+        match try_compile(&format!("class A {{\n{}}}", methods)) {
+            Err(CompileError::TooManyMethods(span)) => assert_eq!(span.column, 7),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+    #[test]
+    fn too_many_locals() {
+        // Slots count from zero, so the last of these is one past `u16::MAX`.
+        let count = u16::MAX as usize + 2;
+        let locals: String = (0..count).map(|i| format!("var v{};", i)).collect();
+        match try_compile(&format!("{{{}print v{};}}", locals, count - 1)) {
+            Err(CompileError::TooManyLocals(span)) => assert_eq!(span.line, 1),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use std::{fmt::Write, io::IsTerminal};
+
+use clap::ValueEnum;
+
+use crate::{
+    compiler::CompileError, interpreter::InterpreterError, parser::ParseError,
+    resolver::ResolveError, scanner::ScanError, tokens::Span, LoxError,
+};
+
+/// When to colour rendered diagnostics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Colour when the output is a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    /// Plain text, e.g. for CI logs.
+    Never,
+}
+impl ColorChoice {
+    /// Whether to colour what is written to `stream`.
+    pub fn enabled(self, stream: impl IsTerminal) -> bool {
+        match self {
+            ColorChoice::Auto => stream.is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+impl Severity {
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+/// A message about a piece of source code, ready to be rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The code of the lint that raised it, if any.
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            span: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+impl From<&ScanError> for Diagnostic {
+    fn from(error: &ScanError) -> Self {
+        match error {
+            ScanError::UnexpectedCharacter(span, c) => {
+                Diagnostic::error(format!("Unexpected character '{}'.", c)).with_span(*span)
+            }
+            ScanError::UnterminatedString(span) => Diagnostic::error("Unterminated string.")
+                .with_span(*span)
+                .with_help("add a closing '\"'"),
+            ScanError::UnexpectedEndOfFile(span) => {
+                Diagnostic::error("Unexpected end of file.").with_span(*span)
+            }
+            ScanError::UnterminatedComment(span) => Diagnostic::error("Unterminated comment.")
+                .with_span(*span)
+                .with_help("add a closing '*/'"),
+            ScanError::InvalidNumber(span, lexeme) => {
+                Diagnostic::error(format!("Invalid number '{}'.", lexeme)).with_span(*span)
+            }
+            ScanError::InvalidEscape(span, text) => {
+                Diagnostic::error(format!("Invalid escape sequence '{}'.", text))
+                    .with_span(*span)
+                    .with_help(r#"valid escapes are \n, \t, \", \\ and \u{...}"#)
+            }
+        }
+    }
+}
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        match error {
+            ParseError::ExpectedExpression(span) => {
+                Diagnostic::error("Expected expression.").with_span(*span)
+            }
+            ParseError::Panic(message, span) => Diagnostic::error(message).with_span(*span),
+        }
+    }
+}
+impl From<&ResolveError> for Diagnostic {
+    fn from(error: &ResolveError) -> Self {
+        let (message, span) = match error {
+            ResolveError::ReadInOwnInitializer(span) => (
+                "Can't read local variable in its own initializer.".to_string(),
+                span,
+            ),
+            ResolveError::AlreadyDeclared(span, name) => (
+                format!("Already a variable named '{}' in this scope.", name),
+                span,
+            ),
+            ResolveError::TopLevelReturn(span) => {
+                ("Can't return from top-level code.".to_string(), span)
+            }
+            ResolveError::ReturnFromInitializer(span) => (
+                "Can't return a value from an initializer.".to_string(),
+                span,
+            ),
+            ResolveError::ThisOutsideClass(span) => {
+                ("Can't use 'this' outside of a class.".to_string(), span)
+            }
+            ResolveError::SuperOutsideClass(span) => {
+                ("Can't use 'super' outside of a class.".to_string(), span)
+            }
+            ResolveError::SuperWithoutSuperclass(span) => (
+                "Can't use 'super' in a class with no superclass.".to_string(),
+                span,
+            ),
+            ResolveError::InheritsFromItself(span) => {
+                ("A class can't inherit from itself.".to_string(), span)
+            }
+        };
+        Diagnostic::error(message).with_span(*span)
+    }
+}
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Self {
+        match error {
+            CompileError::TooManyConstants(span) => {
+                Diagnostic::error("Too many constants in one chunk.").with_span(*span)
+            }
+            CompileError::JumpTooLarge(span) => {
+                Diagnostic::error("Too much code to jump over.").with_span(*span)
+            }
+            CompileError::TooManyLocals(span) => {
+                Diagnostic::error("Too many local variables in scope.").with_span(*span)
+            }
+            CompileError::TooManyMethods(span) => {
+                Diagnostic::error("Too many methods in one class.")
+                    .with_span(*span)
+                    .with_help("a class can have at most 255 methods")
+            }
+        }
+    }
+}
+impl From<&InterpreterError> for Diagnostic {
+    fn from(error: &InterpreterError) -> Self {
+        match error {
+            InterpreterError::RuntimeError(message, span) => {
+                Diagnostic::error(message).with_span(*span)
+            }
+            InterpreterError::Return(_) => Diagnostic::error(error.to_string()),
+        }
+    }
+}
+impl From<&LoxError> for Diagnostic {
+    fn from(error: &LoxError) -> Self {
+        match error {
+            LoxError::ScanError(e) => e.into(),
+            LoxError::ParseError(e) => e.into(),
+            LoxError::ResolveError(e) => e.into(),
+            LoxError::CompileError(e) => e.into(),
+            LoxError::InterpreterError(e) => e.into(),
+            LoxError::Warnings(_) => Diagnostic::warning(error.to_string()),
+            LoxError::IoError(_)
+            | LoxError::ReadlineError(_)
+            | LoxError::Unformatted(_)
+            | LoxError::Multiple(_) => Diagnostic::error(error.to_string()),
+        }
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders diagnostics against the source they refer to, in the style of
+/// `rustc`:
+///
+/// ```text
+/// error: Expected expression.
+///  --> script.lox:1:11
+///   |
+/// 1 | print 1 + ;
+///   |           ^
+/// ```
+pub struct Renderer<'a> {
+    file_name: &'a str,
+    source: &'a str,
+    color: bool,
+}
+impl<'a> Renderer<'a> {
+    pub fn new(file_name: &'a str, source: &'a str) -> Self {
+        Self {
+            file_name,
+            source,
+            color: false,
+        }
+    }
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        let severity = diagnostic.severity;
+        let label = match diagnostic.code {
+            Some(code) => format!("{}[{}]", severity.label(), code),
+            None => severity.label().to_string(),
+        };
+        writeln!(
+            out,
+            "{}{}",
+            self.paint(severity.color(), &label),
+            self.paint(BOLD, &format!(": {}", diagnostic.message))
+        )
+        .unwrap();
+        let gutter = match diagnostic.span {
+            Some(span) => {
+                let gutter = " ".repeat(span.line.to_string().len());
+                self.render_snippet(&mut out, span, &gutter, severity);
+                gutter
+            }
+            None => String::new(),
+        };
+        for note in &diagnostic.notes {
+            writeln!(out, "{} {} note: {}", gutter, self.paint(BLUE, "="), note).unwrap();
+        }
+        if let Some(help) = &diagnostic.help {
+            writeln!(out, "{} {} help: {}", gutter, self.paint(BLUE, "="), help).unwrap();
+        }
+        out
+    }
+    /// Renders `error`, or each of its errors in turn if it holds several.
+    pub fn render_error(&self, error: &LoxError) -> String {
+        match error {
+            LoxError::Multiple(errors) => errors.iter().map(|e| self.render_error(e)).collect(),
+            _ => self.render(&error.into()),
+        }
+    }
+    fn render_snippet(&self, out: &mut String, span: Span, gutter: &str, severity: Severity) {
+        let start = span.start.min(self.source.len());
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        let line = &self.source[line_start..line_end];
+        let bar = self.paint(BLUE, "|");
+        writeln!(
+            out,
+            "{}{} {}:{}:{}",
+            gutter,
+            self.paint(BLUE, "-->"),
+            self.file_name,
+            span.line,
+            span.column
+        )
+        .unwrap();
+        writeln!(out, "{} {}", gutter, bar).unwrap();
+        writeln!(
+            out,
+            "{} {} {}",
+            self.paint(BLUE, &span.line.to_string()),
+            bar,
+            line
+        )
+        .unwrap();
+        // Keep tabs so the carets line up with the text above them.
+        let padding: String = self.source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let end = span.end.clamp(start, line_end);
+        let width = self.source[start..end].chars().count().max(1);
+        writeln!(
+            out,
+            "{} {} {}{}",
+            gutter,
+            bar,
+            padding,
+            self.paint(severity.color(), &"^".repeat(width))
+        )
+        .unwrap();
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn renders_snippet_with_carets() {
+        let source = "var a = 1;\nprint a + \"b\";\n";
+        let diagnostic = Diagnostic::error("Operands must be two numbers or two strings.")
+            .with_span(Span::new(17, 24, 2, 7))
+            .with_note("left operand is a number")
+            .with_help("convert one side first");
+        let rendered = Renderer::new("test.lox", source).render(&diagnostic);
+        assert_eq!(
+            rendered,
+            "error: Operands must be two numbers or two strings.\n \
+             --> test.lox:2:7\n  \
+             |\n\
+             2 | print a + \"b\";\n  \
+             |       ^^^^^^^\n  \
+             = note: left operand is a number\n  \
+             = help: convert one side first\n"
+        );
+    }
+    #[test]
+    fn multi_line_spans_underline_the_first_line() {
+        let source = "{\n  print 1;\n}";
+        let diagnostic = Diagnostic::warning("Unreachable").with_span(Span::new(0, 14, 1, 1));
+        let rendered = Renderer::new("t.lox", source).render(&diagnostic);
+        assert!(rendered.ends_with("1 | {\n  | ^\n"));
+    }
+    #[test]
+    fn spans_at_end_of_input_get_a_single_caret() {
+        let source = "print 1 +";
+        let diagnostic =
+            Diagnostic::error("Expected expression.").with_span(Span::new(9, 9, 1, 10));
+        let rendered = Renderer::new("t.lox", source).render(&diagnostic);
+        assert!(rendered.ends_with("1 | print 1 +\n  |          ^\n"));
+    }
+    #[test]
+    fn color_wraps_labels_in_escape_codes() {
+        let rendered = Renderer::new("t.lox", "")
+            .color(true)
+            .render(&Diagnostic::error("boom"));
+        assert_eq!(
+            rendered,
+            format!("{}error{}{}: boom{}\n", RED, RESET, BOLD, RESET)
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use std::{fmt::Write, hint::unreachable_unchecked};
+
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    ast::{Expr, ExprVisitor, Stmt, StmtVisitor},
+    tokens::{Span, Token, TokenType},
+};
+
+/// How the `tokens` and `ast` commands print what they found.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DumpFormat {
+    /// An indented listing for people.
+    #[default]
+    Tree,
+    /// JSON for tools. Every node is an object with its `kind` and `span`
+    /// first, followed by its fields in declaration order.
+    Json,
+}
+
+/// Lists scanned tokens, one per line.
+pub fn tokens(tokens: &[Token], format: DumpFormat) -> String {
+    match format {
+        DumpFormat::Tree => {
+            let mut out = String::new();
+            for token in tokens {
+                let position = format!("{}:{}", token.span.line, token.span.column);
+                let text = match &token.token_type {
+                    TokenType::String(s) => format!("{:?}", s),
+                    TokenType::Eof => String::new(),
+                    other => other.to_string(),
+                };
+                let line = format!("{:<8}{:<14}{}", position, token.token_type.kind(), text);
+                writeln!(out, "{}", line.trim_end()).unwrap();
+            }
+            out
+        }
+        DumpFormat::Json => {
+            let tokens: Vec<_> = tokens.iter().map(token_json).collect();
+            serde_json::to_string_pretty(&tokens).unwrap() + "\n"
+        }
+    }
+}
+
+/// Prints parsed statements as a tree of nodes.
+pub fn ast(statements: &[Stmt], format: DumpFormat) -> String {
+    let ast = ast_json(statements);
+    match format {
+        DumpFormat::Tree => {
+            let mut out = String::new();
+            for node in ast.as_array().into_iter().flatten() {
+                write_tree(&mut out, node, None, 0);
+            }
+            out
+        }
+        DumpFormat::Json => serde_json::to_string_pretty(&ast).unwrap() + "\n",
+    }
+}
+
+/// The statements as an array of JSON nodes.
+pub fn ast_json(statements: &[Stmt]) -> Value {
+    statements
+        .iter()
+        .map(|stmt| stmt.accept(&mut JsonBuilder))
+        .collect()
+}
+
+fn span_json(span: Span) -> Value {
+    json!({
+        "start": span.start,
+        "end": span.end,
+        "line": span.line,
+        "column": span.column,
+    })
+}
+
+fn token_json(token: &Token) -> Value {
+    let mut object = Map::new();
+    object.insert("kind".into(), token.token_type.kind().into());
+    object.insert("span".into(), span_json(token.span));
+    object.insert("lexeme".into(), token.lexeme().into());
+    match &token.token_type {
+        TokenType::String(s) => {
+            object.insert("value".into(), s.as_str().into());
+        }
+        TokenType::Number(n) => {
+            object.insert("value".into(), (*n).into());
+        }
+        _ => {}
+    }
+    Value::Object(object)
+}
+
+/// A node of `kind` covering `span`, with `fields` after them.
+fn node(kind: &str, span: Span, fields: Value) -> Value {
+    let mut object = Map::new();
+    object.insert("kind".into(), kind.into());
+    object.insert("span".into(), span_json(span));
+    if let Value::Object(fields) = fields {
+        object.extend(fields);
+    }
+    Value::Object(object)
+}
+
+/// Writes `node` and its children, one per line, indented by depth. Scalar
+/// fields and the position go on the node's own line; nested nodes follow,
+/// labelled with the field holding them.
+fn write_tree(out: &mut String, node: &Value, label: Option<&str>, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let label = label.map_or(String::new(), |label| format!("{}: ", label));
+    let Value::Object(object) = node else {
+        writeln!(out, "{}{}{}", indent, label, node).unwrap();
+        return;
+    };
+    let kind = object["kind"].as_str().unwrap_or("?");
+    let mut line = format!("{}{}{}", indent, label, kind);
+    let mut children = Vec::new();
+    for (key, value) in object {
+        match (key.as_str(), value) {
+            ("kind" | "span", _) => {}
+            // Literals are shown as JSON, so `"1"` and `1` differ and `nil`
+            // is `null`. Elsewhere null marks a missing optional field.
+            ("value", value) if kind == "Literal" => write!(line, " value={}", value).unwrap(),
+            (_, Value::Null) => {}
+            (_, Value::Object(_)) => children.push((key, value)),
+            (_, Value::Array(items)) if items.iter().any(Value::is_object) => {
+                children.push((key, value))
+            }
+            (_, Value::Array(items)) => {
+                let items: Vec<_> = items.iter().map(scalar).collect();
+                write!(line, " {}=[{}]", key, items.join(", ")).unwrap();
+            }
+            (_, value) => write!(line, " {}={}", key, scalar(value)).unwrap(),
+        }
+    }
+    if let Some(span) = object.get("span") {
+        write!(line, " @{}:{}", span["line"], span["column"]).unwrap();
+    }
+    writeln!(out, "{}", line).unwrap();
+    for (key, value) in children {
+        match value {
+            Value::Array(items) => {
+                writeln!(out, "{}  {}:", indent, key).unwrap();
+                for item in items {
+                    write_tree(out, item, None, depth + 2);
+                }
+            }
+            _ => write_tree(out, value, Some(key), depth + 1),
+        }
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Converts syntax trees to JSON nodes.
+struct JsonBuilder;
+impl JsonBuilder {
+    fn expr(&mut self, expr: &Expr) -> Value {
+        expr.accept(self)
+    }
+    fn stmt(&mut self, stmt: &Stmt) -> Value {
+        stmt.accept(self)
+    }
+    fn stmts(&mut self, statements: &[Stmt]) -> Value {
+        statements.iter().map(|stmt| self.stmt(stmt)).collect()
+    }
+}
+impl ExprVisitor<Value> for JsonBuilder {
+    fn visit_binary_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                span,
+            } => node(
+                "Binary",
+                *span,
+                json!({
+                    "operator": operator.lexeme(),
+                    "left": self.expr(left),
+                    "right": self.expr(right),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_call_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Call {
+                callee,
+                arguments,
+                span,
+                ..
+            } => node(
+                "Call",
+                *span,
+                json!({
+                    "callee": self.expr(callee),
+                    "arguments": arguments.iter().map(|a| self.expr(a)).collect::<Vec<_>>(),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_grouping_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Grouping { expression, span } => node(
+                "Grouping",
+                *span,
+                json!({ "expression": self.expr(expression) }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_literal_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Literal { value, span } => {
+                let value = match &value.token_type {
+                    TokenType::Number(n) => json!(n),
+                    TokenType::String(s) => json!(s),
+                    TokenType::True => json!(true),
+                    TokenType::False => json!(false),
+                    _ => Value::Null,
+                };
+                node("Literal", *span, json!({ "value": value }))
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_unary_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Unary {
+                operator,
+                right,
+                span,
+            } => node(
+                "Unary",
+                *span,
+                json!({
+                    "operator": operator.lexeme(),
+                    "right": self.expr(right),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Var { name, span, .. } => {
+                node("Variable", *span, json!({ "name": name.lexeme() }))
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_assign_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Assign {
+                name, value, span, ..
+            } => node(
+                "Assign",
+                *span,
+                json!({
+                    "name": name.lexeme(),
+                    "value": self.expr(value),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_logical_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Logical {
+                left,
+                operator,
+                right,
+                span,
+            } => node(
+                "Logical",
+                *span,
+                json!({
+                    "operator": operator.lexeme(),
+                    "left": self.expr(left),
+                    "right": self.expr(right),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_get_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Get { object, name, span } => node(
+                "Get",
+                *span,
+                json!({
+                    "name": name.lexeme(),
+                    "object": self.expr(object),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_set_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Set {
+                object,
+                name,
+                value,
+                span,
+            } => node(
+                "Set",
+                *span,
+                json!({
+                    "name": name.lexeme(),
+                    "object": self.expr(object),
+                    "value": self.expr(value),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_super_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::Super { method, span, .. } => {
+                node("Super", *span, json!({ "method": method.lexeme() }))
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_this_expr(&mut self, expr: &Expr) -> Value {
+        match expr {
+            Expr::This { span, .. } => node("This", *span, json!({})),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
+impl StmtVisitor<Value> for JsonBuilder {
+    fn visit_print_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::Print { expression, span } => node(
+                "Print",
+                *span,
+                json!({ "expression": self.expr(expression) }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_return_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::Return { value, span, .. } => node(
+                "Return",
+                *span,
+                json!({ "value": value.as_ref().map(|value| self.expr(value)) }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_function_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::Function {
+                name,
+                params,
+                body,
+                span,
+            } => node(
+                "Function",
+                *span,
+                json!({
+                    "name": name.lexeme(),
+                    "params": params.iter().map(Token::lexeme).collect::<Vec<_>>(),
+                    "body": self.stmts(body),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_if_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                span,
+            } => node(
+                "If",
+                *span,
+                json!({
+                    "condition": self.expr(condition),
+                    "then_branch": self.stmt(then_branch),
+                    "else_branch": else_branch.as_ref().map(|branch| self.stmt(branch)),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_expr_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::Expression { expression, span } => node(
+                "Expression",
+                *span,
+                json!({ "expression": self.expr(expression) }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::Var {
+                name,
+                initializer,
+                span,
+            } => node(
+                "Var",
+                *span,
+                json!({
+                    "name": name.lexeme(),
+                    "initializer": self.expr(initializer),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_block_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::Block { statements, span } => node(
+                "Block",
+                *span,
+                json!({ "statements": self.stmts(statements) }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_while_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::While {
+                condition,
+                body,
+                span,
+            } => node(
+                "While",
+                *span,
+                json!({
+                    "condition": self.expr(condition),
+                    "body": self.stmt(body),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_class_stmt(&mut self, stmt: &Stmt) -> Value {
+        match stmt {
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                span,
+            } => node(
+                "Class",
+                *span,
+                json!({
+                    "name": name.lexeme(),
+                    "superclass": superclass.as_ref().map(|superclass| self.expr(superclass)),
+                    "methods": self.stmts(methods),
+                }),
+            ),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
@@ -1,41 +1,144 @@
-use std::collections::{HashMap, VecDeque};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use crate::tokens::TokenType;
+use crate::{native::NativeFunction, value::Value};
+/// The local scopes visible at some point, innermost first. Each scope is a
+/// frame of slots indexed in declaration order, as numbered by the resolver.
+/// Scopes are shared, so cloning an environment (as closures do) is cheap and
+/// keeps seeing later writes to them.
 #[derive(Default, Clone, Debug)]
 pub struct Environment {
-    values: VecDeque<HashMap<String, TokenType>>,
+    scope: Option<Rc<Scope>>,
+}
+#[derive(Debug)]
+struct Scope {
+    slots: RefCell<Vec<Value>>,
+    enclosing: Option<Rc<Scope>>,
 }
 impl Environment {
     pub fn new() -> Self {
-        let mut values = VecDeque::new();
-        values.push_front(HashMap::new());
-        Self { values }
-    }
-    pub fn define(&mut self, name: String, value: TokenType) {
-        self.values.front_mut().unwrap().insert(name, value);
-    }
-    pub fn get(&self, name: &str) -> Option<TokenType> {
-        for i in &self.values {
-            if i.contains_key(name) {
-                let val = i.get(name).unwrap().clone();
-                return Some(val);
-            }
+        Self::default()
+    }
+    /// Whether there are no local scopes, so declarations are globals.
+    pub fn is_global(&self) -> bool {
+        self.scope.is_none()
+    }
+    /// Adds a variable to the innermost scope, in the next free slot.
+    pub fn define(&mut self, value: Value) {
+        if let Some(scope) = &self.scope {
+            scope.slots.borrow_mut().push(value);
         }
-        None
-    }
-    pub fn assign(&mut self, name: &str, value: TokenType) -> Option<()> {
-        for i in self.values.iter_mut() {
-            if i.contains_key(name) {
-                i.insert(name.to_string(), value);
-                return Some(());
-            }
+    }
+    fn ancestor(&self, depth: usize) -> Option<&Rc<Scope>> {
+        let mut scope = self.scope.as_ref()?;
+        for _ in 0..depth {
+            scope = scope.enclosing.as_ref()?;
         }
-        None
+        Some(scope)
+    }
+    /// Reads the variable in `slot` of the scope `depth` levels out.
+    pub fn get_at(&self, depth: usize, slot: usize) -> Option<Value> {
+        self.ancestor(depth)?.slots.borrow().get(slot).cloned()
+    }
+    pub fn assign_at(&mut self, depth: usize, slot: usize, value: Value) -> Option<()> {
+        *self.ancestor(depth)?.slots.borrow_mut().get_mut(slot)? = value;
+        Some(())
     }
     pub fn enter_scope(&mut self) {
-        self.values.push_front(HashMap::new());
+        self.scope = Some(Rc::new(Scope {
+            slots: RefCell::new(Vec::new()),
+            enclosing: self.scope.take(),
+        }));
     }
     pub fn exit_scope(&mut self) {
-        self.values.pop_front();
+        self.scope = self.scope.take().and_then(|scope| scope.enclosing.clone());
+    }
+}
+
+/// Global variables. Each name is interned to a fixed index the first time
+/// it is seen, so resolved code reads globals without hashing their names.
+#[derive(Default, Debug)]
+pub struct Globals {
+    indices: HashMap<Rc<str>, usize>,
+    names: Vec<Rc<str>>,
+    /// `None` until the global is defined.
+    values: Vec<Option<Value>>,
+}
+impl Globals {
+    /// The index of global `name`, allocating one if it is new.
+    pub fn intern(&mut self, name: &str) -> usize {
+        if let Some(&index) = self.indices.get(name) {
+            return index;
+        }
+        let name: Rc<str> = name.into();
+        let index = self.names.len();
+        self.indices.insert(name.clone(), index);
+        self.names.push(name);
+        self.values.push(None);
+        index
+    }
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).copied()
+    }
+    pub fn name(&self, index: usize) -> &str {
+        &self.names[index]
+    }
+    pub fn get(&self, index: usize) -> Option<Value> {
+        self.values.get(index)?.clone()
+    }
+    pub fn define(&mut self, index: usize, value: Value) {
+        self.values[index] = Some(value);
+    }
+    /// Assigns to an already defined global, failing if it is undefined.
+    pub fn assign(&mut self, index: usize, value: Value) -> Option<()> {
+        *self.values.get_mut(index)?.as_mut()? = value;
+        Some(())
+    }
+    /// Defines `native` as a global under its own name.
+    pub fn define_native(&mut self, native: NativeFunction) {
+        let index = self.intern(native.name());
+        self.define(index, Value::Native(Rc::new(native)));
+    }
+    /// Every defined global with its value, in order of first use.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| Some((name.as_ref(), value.as_ref()?)))
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn slots_are_addressed_by_depth() {
+        let mut environment = Environment::new();
+        assert!(environment.is_global());
+        environment.enter_scope();
+        environment.define(Value::from(1.0));
+        let closure = environment.clone();
+        environment.enter_scope();
+        environment.define(Value::from(2.0));
+        assert_eq!(environment.get_at(1, 0), Some(Value::from(1.0)));
+        assert_eq!(environment.get_at(0, 0), Some(Value::from(2.0)));
+        environment.assign_at(1, 0, Value::from(3.0)).unwrap();
+        // The closure shares the outer scope, so it sees the write.
+        assert_eq!(closure.get_at(0, 0), Some(Value::from(3.0)));
+        assert_eq!(environment.get_at(2, 0), None);
+        environment.exit_scope();
+        environment.exit_scope();
+        assert!(environment.is_global());
+    }
+    #[test]
+    fn globals_are_interned() {
+        let mut globals = Globals::default();
+        let a = globals.intern("a");
+        assert_eq!(globals.intern("b"), a + 1);
+        assert_eq!(globals.intern("a"), a);
+        assert_eq!(globals.get(a), None);
+        assert_eq!(globals.assign(a, Value::Nil), None);
+        globals.define(a, Value::from(true));
+        globals.assign(a, Value::from(false)).unwrap();
+        assert_eq!(globals.get(a), Some(Value::from(false)));
+        assert_eq!(globals.iter().count(), 1);
     }
 }
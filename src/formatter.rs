@@ -0,0 +1,605 @@
+use std::{hint::unreachable_unchecked, mem};
+
+use crate::{
+    ast::{Expr, ExprVisitor, Stmt, StmtVisitor},
+    scanner::{Dialect, Scanner},
+    tokens::{Comment, Span, Token, TokenType},
+};
+
+const INDENT: &str = "  ";
+
+/// How tightly an expression binds, loosest first, following the grammar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+impl Precedence {
+    fn of(expr: &Expr) -> Precedence {
+        match expr {
+            Expr::Assign { .. } | Expr::Set { .. } => Precedence::Assignment,
+            Expr::Logical { operator, .. } | Expr::Binary { operator, .. } => {
+                match operator.token_type {
+                    TokenType::Or => Precedence::Or,
+                    TokenType::And => Precedence::And,
+                    TokenType::EqualEqual | TokenType::BangEqual => Precedence::Equality,
+                    TokenType::Plus | TokenType::Minus => Precedence::Term,
+                    TokenType::Star | TokenType::Slash => Precedence::Factor,
+                    _ => Precedence::Comparison,
+                }
+            }
+            Expr::Unary { .. } => Precedence::Unary,
+            Expr::Call { .. } | Expr::Get { .. } => Precedence::Call,
+            Expr::Grouping { .. }
+            | Expr::Literal { .. }
+            | Expr::Super { .. }
+            | Expr::This { .. }
+            | Expr::Var { .. } => Precedence::Primary,
+        }
+    }
+    /// The next tighter level, which the right operand of a left-associative
+    /// operator needs.
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+/// Turns parsed statements back into canonically laid out source: two
+/// spaces of indentation, one statement per line, at most one blank line in
+/// a row. Comments are put back where they were, either on a line of their
+/// own or after the statement they trail.
+///
+/// The parser desugars `for` loops, so they are recognised by their spans,
+/// which all start at the `for` keyword, and printed as written.
+pub struct Formatter<'a> {
+    source: &'a str,
+    dialect: Dialect,
+    comments: &'a [Comment],
+    /// The next comment to print.
+    next_comment: usize,
+    out: String,
+    depth: usize,
+    /// Where the last statement or comment printed ended, to tell whether a
+    /// blank line followed it.
+    last_end: usize,
+    /// Whether nothing has been printed yet in the current block.
+    at_block_start: bool,
+}
+impl<'a> Formatter<'a> {
+    pub fn new(source: &'a str, comments: &'a [Comment], dialect: Dialect) -> Self {
+        Self {
+            source,
+            dialect,
+            comments,
+            next_comment: 0,
+            out: String::new(),
+            depth: 0,
+            last_end: 0,
+            at_block_start: true,
+        }
+    }
+    pub fn format(mut self, statements: &[Stmt]) -> String {
+        self.items(statements, self.source.len(), Formatter::stmt);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+    fn stmt(&mut self, stmt: &Stmt) {
+        stmt.accept(self)
+    }
+    /// Prints `expr`, in parentheses if it binds looser than `min`.
+    fn expr(&mut self, expr: &Expr, min: Precedence) -> String {
+        let text = expr.accept(self);
+        if Precedence::of(expr) < min {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+    /// Prints `stmt` into a string instead of the output.
+    fn inline(&mut self, stmt: &Stmt) -> String {
+        let out = mem::take(&mut self.out);
+        self.stmt(stmt);
+        mem::replace(&mut self.out, out)
+    }
+    fn text(&self, span: Span) -> &'a str {
+        &self.source[span.start..span.end]
+    }
+    /// Starts a new line at the current indentation, keeping a single blank
+    /// line if the source had any before `start`.
+    fn new_line(&mut self, start: usize) {
+        let gap = &self.source[self.last_end.min(start)..start];
+        if !self.out.is_empty() {
+            self.out.push('\n');
+            if !self.at_block_start && gap.matches('\n').count() > 1 {
+                self.out.push('\n');
+            }
+        }
+        self.at_block_start = false;
+        self.out.push_str(&INDENT.repeat(self.depth));
+    }
+    fn peek_comment(&self) -> Option<&'a Comment> {
+        self.comments.get(self.next_comment)
+    }
+    /// Prints the comments before `offset`, each on its own line.
+    fn comments_before(&mut self, offset: usize) {
+        while let Some(comment) = self.peek_comment().filter(|c| c.span.start < offset) {
+            self.new_line(comment.span.start);
+            self.out.push_str(&comment.text);
+            self.last_end = comment.span.end;
+            self.next_comment += 1;
+        }
+    }
+    /// Moves the comments left inside the statement printed from `line`
+    /// onwards and ending at `end` to lines of their own before it. Printed
+    /// after it, they would be read as belonging to the wrong code.
+    fn inner_comments(&mut self, line: usize, end: usize) {
+        let mut lines = String::new();
+        while let Some(comment) = self.peek_comment().filter(|c| c.span.start < end) {
+            lines.push_str(&INDENT.repeat(self.depth));
+            lines.push_str(&comment.text);
+            lines.push('\n');
+            self.next_comment += 1;
+        }
+        let line = line + self.out[line..].len() - self.out[line..].trim_start_matches('\n').len();
+        self.out.insert_str(line, &lines);
+    }
+    /// Appends the comments after the statement that just ended at `end` on
+    /// the same line, before `next` starts.
+    fn trailing_comments(&mut self, end: usize, next: usize) {
+        while let Some(comment) = self
+            .peek_comment()
+            .filter(|c| c.span.start < next && !self.source[end..c.span.start].contains('\n'))
+        {
+            self.out.push(' ');
+            self.out.push_str(&comment.text);
+            self.last_end = self.last_end.max(comment.span.end);
+            self.next_comment += 1;
+        }
+    }
+    /// Prints a list of statements, one per line, with the comments among
+    /// them. The list ends at `end`, before a closing brace or the end of
+    /// the file.
+    fn items(&mut self, statements: &[Stmt], end: usize, print: fn(&mut Self, &Stmt)) {
+        for (i, stmt) in statements.iter().enumerate() {
+            let span = stmt.span();
+            self.comments_before(span.start);
+            let line = self.out.len();
+            self.new_line(span.start);
+            print(self, stmt);
+            self.inner_comments(line, span.end);
+            self.last_end = span.end;
+            let next = statements.get(i + 1).map_or(end, |next| next.span().start);
+            self.trailing_comments(span.end, next);
+        }
+        self.comments_before(end);
+    }
+    /// Prints `{`, the statements, and `}` on a line of its own.
+    fn block(&mut self, statements: &[Stmt], span: Span, print: fn(&mut Self, &Stmt)) {
+        let end = span.end.saturating_sub(1);
+        let has_comments = self.peek_comment().is_some_and(|c| c.span.start < end);
+        if statements.is_empty() && !has_comments {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push('{');
+        self.depth += 1;
+        self.at_block_start = true;
+        self.items(statements, end, print);
+        self.depth -= 1;
+        self.out.push('\n');
+        self.out.push_str(&INDENT.repeat(self.depth));
+        self.out.push('}');
+    }
+    /// Prints the body of an `if`, `else` or loop after its header.
+    fn body(&mut self, stmt: &Stmt) {
+        self.out.push(' ');
+        self.stmt(stmt);
+    }
+    fn function(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Function {
+                name,
+                params,
+                body,
+                span,
+            } => {
+                let params: Vec<_> = params.iter().map(Token::lexeme).collect();
+                self.out
+                    .push_str(&format!("{}({}) ", name.lexeme(), params.join(", ")));
+                self.block(body, *span, Formatter::stmt);
+            }
+            _ => self.stmt(stmt),
+        }
+    }
+    /// Whether the source at `span` is exactly the literal `value`.
+    fn is_written(&self, value: &Token, span: Span) -> bool {
+        let (tokens, errors) = Scanner::with_dialect(self.text(span), self.dialect).scan_tokens();
+        errors.is_empty()
+            && matches!(&tokens[..], [token, _eof] if token.token_type == value.token_type)
+    }
+    /// Whether a statement starting at `span` was desugared from a `for`.
+    fn is_for(&self, span: Span) -> bool {
+        let rest = &self.source[span.start.min(self.source.len())..];
+        rest.strip_prefix("for")
+            .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+    }
+    /// Prints a `for` loop from its desugared parts: the initializer, if any,
+    /// and the `while` loop built from the rest.
+    fn for_loop(&mut self, initializer: Option<&Stmt>, stmt: &Stmt) {
+        let Stmt::While {
+            condition, body, ..
+        } = stmt
+        else {
+            return self.stmt(stmt);
+        };
+        let initializer = initializer.map_or(";".to_string(), |init| self.inline(init));
+        // A missing condition is filled in with `true` at the second `;`.
+        let condition = match condition {
+            Expr::Literal { span, .. } if self.text(*span) == ";" => String::new(),
+            condition => format!(" {}", self.expr(condition, Precedence::Assignment)),
+        };
+        let (body, increment) = match body.as_ref() {
+            Stmt::Block { statements, span } if self.is_for(*span) && statements.len() == 2 => {
+                match &statements[1] {
+                    Stmt::Expression { expression, .. } => (
+                        &statements[0],
+                        format!(" {}", self.expr(expression, Precedence::Assignment)),
+                    ),
+                    _ => (body.as_ref(), String::new()),
+                }
+            }
+            body => (body, String::new()),
+        };
+        self.out
+            .push_str(&format!("for ({}{};{})", initializer, condition, increment));
+        self.body(body);
+    }
+}
+impl StmtVisitor<()> for Formatter<'_> {
+    fn visit_print_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print { expression, .. } => {
+                let expression = self.expr(expression, Precedence::Assignment);
+                self.out.push_str(&format!("print {};", expression));
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_return_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Return { value, .. } => match value {
+                Some(value) => {
+                    let value = self.expr(value, Precedence::Assignment);
+                    self.out.push_str(&format!("return {};", value));
+                }
+                None => self.out.push_str("return;"),
+            },
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_function_stmt(&mut self, stmt: &Stmt) {
+        self.out.push_str("fun ");
+        self.function(stmt);
+    }
+    fn visit_if_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                let condition = self.expr(condition, Precedence::Assignment);
+                self.out.push_str(&format!("if ({})", condition));
+                self.body(then_branch);
+                if let Some(else_branch) = else_branch {
+                    if matches!(**then_branch, Stmt::Block { .. }) {
+                        self.out.push_str(" else");
+                    } else {
+                        self.out.push('\n');
+                        self.out.push_str(&INDENT.repeat(self.depth));
+                        self.out.push_str("else");
+                    }
+                    self.body(else_branch);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_expr_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression, .. } => {
+                let expression = self.expr(expression, Precedence::Assignment);
+                self.out.push_str(&format!("{};", expression));
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Var {
+                name, initializer, ..
+            } => match initializer {
+                // `var x;` gets a `nil` initializer at the name.
+                Expr::Literal { span, .. } if *span == name.span => {
+                    self.out.push_str(&format!("var {};", name.lexeme()))
+                }
+                initializer => {
+                    let initializer = self.expr(initializer, Precedence::Assignment);
+                    self.out
+                        .push_str(&format!("var {} = {};", name.lexeme(), initializer));
+                }
+            },
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_block_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, span } => match &statements[..] {
+                [initializer, while_loop] if self.is_for(*span) => {
+                    self.for_loop(Some(initializer), while_loop)
+                }
+                _ => self.block(statements, *span, Formatter::stmt),
+            },
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_while_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::While {
+                condition,
+                body,
+                span,
+            } => {
+                if self.is_for(*span) {
+                    return self.for_loop(None, stmt);
+                }
+                let condition = self.expr(condition, Precedence::Assignment);
+                self.out.push_str(&format!("while ({})", condition));
+                self.body(body);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_class_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                span,
+            } => {
+                self.out.push_str(&format!("class {} ", name.lexeme()));
+                if let Some(superclass) = superclass {
+                    let superclass = self.expr(superclass, Precedence::Primary);
+                    self.out.push_str(&format!("< {} ", superclass));
+                }
+                self.block(methods, *span, Formatter::function);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
+impl ExprVisitor<String> for Formatter<'_> {
+    fn visit_binary_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let precedence = Precedence::of(expr);
+                let left = self.expr(left, precedence);
+                let right = self.expr(right, precedence.next());
+                format!("{} {} {}", left, operator.lexeme(), right)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_call_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                let callee = self.expr(callee, Precedence::Call);
+                let arguments: Vec<_> = arguments
+                    .iter()
+                    .map(|argument| self.expr(argument, Precedence::Assignment))
+                    .collect();
+                format!("{}({})", callee, arguments.join(", "))
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_grouping_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Grouping { expression, .. } => {
+                format!("({})", self.expr(expression, Precedence::Assignment))
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_literal_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Literal { value, span } => match &value.token_type {
+                // Numbers and strings are kept as written, so escapes and
+                // raw strings survive, unless the literal was folded from a
+                // longer expression.
+                TokenType::Number(_) | TokenType::String(_) if self.is_written(value, *span) => {
+                    self.text(*span).to_string()
+                }
+                TokenType::String(s) => escape(s),
+                other => other.to_string(),
+            },
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_unary_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Unary {
+                operator, right, ..
+            } => {
+                let right = self.expr(right, Precedence::Unary);
+                format!("{}{}", operator.lexeme(), right)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Var { name, .. } => name.lexeme(),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_assign_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Assign { name, value, .. } => {
+                let value = self.expr(value, Precedence::Assignment);
+                format!("{} = {}", name.lexeme(), value)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_logical_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let precedence = Precedence::of(expr);
+                let left = self.expr(left, precedence);
+                let right = self.expr(right, precedence.next());
+                format!("{} {} {}", left, operator.lexeme(), right)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_get_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Get { object, name, .. } => {
+                let object = self.expr(object, Precedence::Call);
+                format!("{}.{}", object, name.lexeme())
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_set_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                let object = self.expr(object, Precedence::Call);
+                let value = self.expr(value, Precedence::Assignment);
+                format!("{}.{} = {}", object, name.lexeme(), value)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_super_expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Super { method, .. } => format!("super.{}", method.lexeme()),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_this_expr(&mut self, _expr: &Expr) -> String {
+        "this".to_string()
+    }
+}
+
+/// Writes `s` as a string literal that scans back to `s`.
+fn escape(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{optimizer::optimize, parser::parse_source};
+    fn format(source: &str) -> String {
+        let (stmts, comments) = parse_source(source, Dialect::Lox);
+        Formatter::new(source, &comments, Dialect::Lox).format(&stmts)
+    }
+    #[test]
+    fn formatted_code_is_left_alone() {
+        let source = "var x;\nfor (var i = 0; i < 3; i = i + 1) print i;\nfor (;;) {}\nfor (; x;) x = nil;\nif (x) {\n  print \"a\\n\";\n} else print 1.5;\nclass A < B {\n  init() {\n    super.init(this.x);\n  }\n}\n";
+        assert_eq!(format(source), source);
+    }
+    #[test]
+    fn layout_is_normalized() {
+        assert_eq!(
+            format("fun  f(a,b){return a+b;}\n\n\n\nprint f( 1,2 ) ;if(x)print 1;else{}"),
+            "fun f(a, b) {\n  return a + b;\n}\n\nprint f(1, 2);\nif (x) print 1;\nelse {}\n"
+        );
+    }
+    #[test]
+    fn comments_are_kept() {
+        let source = "// leading\nvar a = 1; // trailing\n{\n  /* inside */\n  print a;\n  // before brace\n}\n// end\n";
+        assert_eq!(format(source), source);
+    }
+    #[test]
+    fn comments_inside_statements_move_before_them() {
+        assert_eq!(
+            format("if (x) print 1; // one\nelse print 2; // two\n"),
+            "// one\nif (x) print 1;\nelse print 2; // two\n"
+        );
+        assert_eq!(
+            format("{\n  print 1 + // c\n    2;\n}\n"),
+            "{\n  // c\n  print 1 + 2;\n}\n"
+        );
+    }
+    #[test]
+    fn parentheses_follow_precedence() {
+        // The optimizer unwraps groupings, so they have to be put back.
+        let source = "print (a + b) * -(c - d);\nprint a - (b - c);\nprint (a - b) - c;\nprint (a or b) and !(c == d);\nprint (a = b).c;\n";
+        let (stmts, comments) = parse_source(source, Dialect::Lox);
+        let formatted = Formatter::new(source, &comments, Dialect::Lox).format(&optimize(stmts));
+        assert_eq!(
+            formatted,
+            "print (a + b) * -(c - d);\nprint a - (b - c);\nprint a - b - c;\nprint (a or b) and !(c == d);\nprint (a = b).c;\n"
+        );
+    }
+    #[test]
+    fn folded_literals_are_printed_by_value() {
+        let source = "print 1 + 2;\nprint \"a\" + \"\\\"b\";\n";
+        let (stmts, comments) = parse_source(source, Dialect::Lox);
+        let formatted = Formatter::new(source, &comments, Dialect::Lox).format(&optimize(stmts));
+        assert_eq!(formatted, "print 3;\nprint \"a\\\"b\";\n");
+    }
+}
@@ -0,0 +1,117 @@
+use std::{fmt::Debug, rc::Rc};
+
+use crate::{
+    ast::Stmt,
+    chunk::Chunk,
+    environment::Environment,
+    interpreter::{Interpreter, InterpreterError},
+    tokens::{Span, Token},
+    value::Value,
+};
+
+pub trait LoxCallable {
+    fn arity(&self) -> usize;
+    /// Calls with as many `arguments` as the arity. `span` is the call, for
+    /// errors the callee reports about itself rather than its code.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, InterpreterError>;
+}
+
+/// What a function runs when it is called, depending on the backend that
+/// created it.
+#[derive(Clone)]
+pub enum Code {
+    /// Statements run by the tree-walking [`Interpreter`].
+    Tree {
+        params: Vec<Token>,
+        body: Rc<Vec<Stmt>>,
+    },
+    /// A chunk run by the [`Vm`](crate::vm::Vm).
+    Bytecode { arity: usize, chunk: Rc<Chunk> },
+}
+
+/// A user-defined function together with the environment it was declared in.
+#[derive(Clone)]
+pub struct LoxFunction {
+    name: Token,
+    code: Code,
+    closure: Environment,
+    is_initializer: bool,
+}
+impl LoxFunction {
+    pub fn new(name: Token, code: Code, closure: Environment, is_initializer: bool) -> Self {
+        Self {
+            name,
+            code,
+            closure,
+            is_initializer,
+        }
+    }
+    /// Returns a copy of this method whose closure has `this` bound to `instance`.
+    pub fn bind(&self, instance: Value) -> LoxFunction {
+        let mut closure = self.closure.clone();
+        closure.enter_scope();
+        closure.define(instance);
+        LoxFunction {
+            closure,
+            ..self.clone()
+        }
+    }
+    pub fn name(&self) -> String {
+        self.name.lexeme()
+    }
+    pub fn code(&self) -> &Code {
+        &self.code
+    }
+    pub fn closure(&self) -> &Environment {
+        &self.closure
+    }
+    pub fn is_initializer(&self) -> bool {
+        self.is_initializer
+    }
+}
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> usize {
+        match &self.code {
+            Code::Tree { params, .. } => params.len(),
+            Code::Bytecode { arity, .. } => *arity,
+        }
+    }
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        _span: Span,
+    ) -> Result<Value, InterpreterError> {
+        let Code::Tree { body, .. } = &self.code else {
+            unreachable!("compiled functions are only called by the VM");
+        };
+        let mut environment = self.closure.clone();
+        environment.enter_scope();
+        // Parameters take the first slots of the call's scope, in order.
+        for argument in arguments {
+            environment.define(argument);
+        }
+        let value = match interpreter.execute_in_environment(body, environment) {
+            Ok(()) => Value::Nil,
+            Err(InterpreterError::Return(value)) => value,
+            Err(e) => return Err(e),
+        };
+        if self.is_initializer {
+            // Bound methods keep `this` alone in their innermost scope.
+            return Ok(self.closure.get_at(0, 0).unwrap_or(Value::Nil));
+        }
+        Ok(value)
+    }
+}
+// Printing the closure would recurse forever for any function that can see
+// itself, so only the name is shown.
+impl Debug for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
@@ -1,33 +1,101 @@
-use std::hint::unreachable_unchecked;
+use std::{collections::HashMap, hint::unreachable_unchecked, mem, rc::Rc};
 
 use thiserror::Error;
 
 use crate::{
-    ast::{Expr, ExprVisitor, Stmt, StmtVisitor},
-    environment::Environment,
-    tokens::TokenType,
+    ast::{Binding, Expr, ExprVisitor, Stmt, StmtVisitor},
+    class::LoxClass,
+    environment::{Environment, Globals},
+    function::{Code, LoxCallable, LoxFunction},
+    native,
+    tokens::{Span, Token, TokenType},
+    value::Value,
+    vm::FRAMES_MAX,
 };
 #[derive(Error, Debug)]
 pub enum InterpreterError {
-    #[error("{0}\n[line {1}]")]
-    RuntimeError(String, usize),
+    #[error("{0}\n[{1}]")]
+    RuntimeError(String, Span),
+    /// Unwinds the stack out of a function body carrying its return value.
+    #[error("Can't return from top-level code.")]
+    Return(Value),
 }
-type InterpreterResult = Result<TokenType, InterpreterError>;
+type InterpreterResult = Result<Value, InterpreterError>;
+/// How much native stack a call must have left, or it gets a new segment
+/// of `STACK_GROWTH` bytes. One call goes through a few dozen visitor frames,
+/// which are large in debug builds.
+const STACK_RED_ZONE: usize = 256 << 10;
+const STACK_GROWTH: usize = 4 << 20;
 #[derive(Default)]
 pub struct Interpreter {
     environment: Environment,
+    globals: Globals,
+    /// How many calls are being run, to stop runaway recursion at the same
+    /// depth as the VM.
+    depth: usize,
 }
 impl Interpreter {
     pub fn new() -> Self {
+        let mut globals = Globals::default();
+        for native in native::standard_library() {
+            globals.define_native(native);
+        }
         Self {
             environment: Environment::new(),
+            globals,
+            depth: 0,
+        }
+    }
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+    /// The globals table, for resolving code before it is run here.
+    pub fn globals_mut(&mut self) -> &mut Globals {
+        &mut self.globals
+    }
+    /// Declares `name` in the innermost scope, or as a global at the top
+    /// level.
+    fn define(&mut self, name: &Token, value: Value) {
+        if self.environment.is_global() {
+            let index = self.globals.intern(&name.lexeme());
+            self.globals.define(index, value);
+        } else {
+            self.environment.define(value);
+        }
+    }
+    fn undefined_variable(name: &Token) -> InterpreterError {
+        InterpreterError::RuntimeError(
+            format!("Undefined variable '{}'.", name.lexeme()),
+            name.span,
+        )
+    }
+    pub(crate) fn bind_method(
+        class: &LoxClass,
+        instance: Value,
+        name: &Token,
+    ) -> InterpreterResult {
+        match class.find_method(&name.lexeme()) {
+            Some(method) => Ok(Value::Function(Rc::new(method.bind(instance)))),
+            None => Err(InterpreterError::RuntimeError(
+                format!("Undefined property '{}'.", name.lexeme()),
+                name.span,
+            )),
         }
     }
     pub fn interpret(&mut self, stmt: Vec<Stmt>) -> InterpreterResult {
         for stmt in stmt {
-            self.execute(&stmt)?;
+            match self.execute(&stmt) {
+                Ok(()) => {}
+                Err(InterpreterError::Return(_)) => {
+                    return Err(InterpreterError::RuntimeError(
+                        "Can't return from top-level code.".to_string(),
+                        stmt.span(),
+                    ))
+                }
+                Err(e) => return Err(e),
+            }
         }
-        Ok(TokenType::Nil)
+        Ok(Value::Nil)
     }
     fn execute(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
         stmt.accept(self)
@@ -35,30 +103,44 @@ impl Interpreter {
     fn evaluate(&mut self, expr: &Expr) -> InterpreterResult {
         expr.accept(self)
     }
-    fn get_number(tok: TokenType, err_msg: String) -> Result<f64, InterpreterError> {
-        match tok {
-            TokenType::Number(n) => Ok(n),
-            _ => Err(InterpreterError::RuntimeError(err_msg, 0)),
+    fn get_number(value: Value, err_msg: String, span: Span) -> Result<f64, InterpreterError> {
+        match value {
+            Value::Number(n) => Ok(n),
+            _ => Err(InterpreterError::RuntimeError(err_msg, span)),
         }
     }
-    fn get_numbers(t1: TokenType, t2: TokenType) -> Result<(f64, f64), InterpreterError> {
-        let n1 = Interpreter::get_number(t1, "Left operand must be a number.".to_string())?;
-        let n2 = Interpreter::get_number(t2, "Right operand must be a number.".to_string())?;
+    /// Unwraps both operands of a binary expression, blaming the offending one.
+    fn get_numbers(
+        (t1, s1): (Value, Span),
+        (t2, s2): (Value, Span),
+    ) -> Result<(f64, f64), InterpreterError> {
+        let n1 = Interpreter::get_number(t1, "Left operand must be a number.".to_string(), s1)?;
+        let n2 = Interpreter::get_number(t2, "Right operand must be a number.".to_string(), s2)?;
         Ok((n1, n2))
     }
     fn execute_block(&mut self, statements: &[Stmt]) -> Result<(), InterpreterError> {
         self.environment.enter_scope();
-        for stmt in statements {
-            self.execute(stmt)?;
-        }
+        let result = statements.iter().try_for_each(|stmt| self.execute(stmt));
         self.environment.exit_scope();
-        Ok(())
+        result
+    }
+    /// Runs `statements` directly in `environment`, restoring the current
+    /// environment afterwards even if execution unwinds with an error.
+    pub(crate) fn execute_in_environment(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> Result<(), InterpreterError> {
+        let previous = mem::replace(&mut self.environment, environment);
+        let result = statements.iter().try_for_each(|stmt| self.execute(stmt));
+        self.environment = previous;
+        result
     }
 }
 impl StmtVisitor<Result<(), InterpreterError>> for Interpreter {
     fn visit_print_stmt(&mut self, stmt: &crate::ast::Stmt) -> Result<(), InterpreterError> {
         match stmt {
-            Stmt::Print { expression } => {
+            Stmt::Print { expression, .. } => {
                 let value = self.evaluate(expression)?;
                 println!("{}", value);
                 Ok(())
@@ -66,14 +148,99 @@ impl StmtVisitor<Result<(), InterpreterError>> for Interpreter {
             _ => unsafe { unreachable_unchecked() },
         }
     }
+    fn visit_return_stmt(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
+        match stmt {
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
+                };
+                Err(InterpreterError::Return(value))
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_function_stmt(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
+        match stmt {
+            Stmt::Function {
+                name, params, body, ..
+            } => {
+                let code = Code::Tree {
+                    params: params.clone(),
+                    body: body.clone(),
+                };
+                let function =
+                    LoxFunction::new(name.clone(), code, self.environment.clone(), false);
+                self.define(name, Value::Function(Rc::new(function)));
+                Ok(())
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_class_stmt(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
+        match stmt {
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let superclass = match superclass {
+                    Some(expr) => match self.evaluate(expr)? {
+                        Value::Class(class) => Some(class),
+                        _ => {
+                            return Err(InterpreterError::RuntimeError(
+                                "Superclass must be a class.".to_string(),
+                                expr.span(),
+                            ))
+                        }
+                    },
+                    None => None,
+                };
+                let mut closure = self.environment.clone();
+                if let Some(superclass) = &superclass {
+                    closure.enter_scope();
+                    closure.define(Value::Class(superclass.clone()));
+                }
+                let methods = methods
+                    .iter()
+                    .filter_map(|method| match method {
+                        Stmt::Function {
+                            name, params, body, ..
+                        } => {
+                            let code = Code::Tree {
+                                params: params.clone(),
+                                body: body.clone(),
+                            };
+                            let function = LoxFunction::new(
+                                name.clone(),
+                                code,
+                                closure.clone(),
+                                name.lexeme() == "init",
+                            );
+                            Some((name.lexeme(), Rc::new(function)))
+                        }
+                        _ => None,
+                    })
+                    .collect::<HashMap<_, _>>();
+                // Methods share the scope the class is declared in, so they
+                // still see the class once it is defined.
+                let class = LoxClass::new(name.lexeme(), superclass, methods);
+                self.define(name, Value::Class(Rc::new(class)));
+                Ok(())
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
     fn visit_if_stmt(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
         match stmt {
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
+                ..
             } => {
-                if TryInto::<bool>::try_into(self.evaluate(condition)?).unwrap() {
+                if self.evaluate(condition)?.is_truthy() {
                     self.execute(then_branch)?;
                 } else if let Some(else_branch) = else_branch {
                     self.execute(else_branch)?;
@@ -85,7 +252,7 @@ impl StmtVisitor<Result<(), InterpreterError>> for Interpreter {
     }
     fn visit_expr_stmt(&mut self, stmt: &crate::ast::Stmt) -> Result<(), InterpreterError> {
         match stmt {
-            Stmt::Expression { expression } => {
+            Stmt::Expression { expression, .. } => {
                 self.evaluate(expression)?;
                 Ok(())
             }
@@ -95,9 +262,11 @@ impl StmtVisitor<Result<(), InterpreterError>> for Interpreter {
 
     fn visit_var_stmt(&mut self, stmt: &crate::ast::Stmt) -> Result<(), InterpreterError> {
         match stmt {
-            Stmt::Var { name, initializer } => {
+            Stmt::Var {
+                name, initializer, ..
+            } => {
                 let value = self.evaluate(initializer)?;
-                self.environment.define(name.to_string(), value);
+                self.define(name, value);
                 Ok(())
             }
             _ => unsafe { unreachable_unchecked() },
@@ -106,15 +275,17 @@ impl StmtVisitor<Result<(), InterpreterError>> for Interpreter {
 
     fn visit_block_stmt(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
         match stmt {
-            Stmt::Block { statements } => self.execute_block(statements),
+            Stmt::Block { statements, .. } => self.execute_block(statements),
             _ => unsafe { unreachable_unchecked() },
         }
     }
 
     fn visit_while_stmt(&mut self, stmt: &Stmt) -> Result<(), InterpreterError> {
         match stmt {
-            Stmt::While { condition, body } => {
-                while TryInto::<bool>::try_into(self.evaluate(condition)?).unwrap() {
+            Stmt::While {
+                condition, body, ..
+            } => {
+                while self.evaluate(condition)?.is_truthy() {
                     self.execute(body)?;
                 }
                 Ok(())
@@ -123,94 +294,148 @@ impl StmtVisitor<Result<(), InterpreterError>> for Interpreter {
         }
     }
 }
-impl ExprVisitor<Result<TokenType, InterpreterError>> for Interpreter {
+impl ExprVisitor<Result<Value, InterpreterError>> for Interpreter {
     fn visit_binary_expr(&mut self, expr: &Expr) -> InterpreterResult {
         let (left, operator, right) = match expr {
             Expr::Binary {
                 left,
                 operator,
                 right,
+                ..
             } => (left, operator, right),
             _ => unsafe { unreachable_unchecked() },
         };
-        let left = self.evaluate(left)?;
-        let right = self.evaluate(right)?;
+        let (left, right) = (
+            (self.evaluate(left)?, left.span()),
+            (self.evaluate(right)?, right.span()),
+        );
         match operator.token_type {
             TokenType::Minus => {
                 let (nl, nr) = Interpreter::get_numbers(left, right)?;
-                Ok(TokenType::Number(nl - nr))
+                Ok(Value::Number(nl - nr))
             }
             TokenType::Slash => {
                 let (nl, nr) = Interpreter::get_numbers(left, right)?;
-                Ok(TokenType::Number(nl / nr))
+                Ok(Value::Number(nl / nr))
             }
             TokenType::Star => {
                 let (nl, nr) = Interpreter::get_numbers(left, right)?;
-                Ok(TokenType::Number(nl * nr))
+                Ok(Value::Number(nl * nr))
             }
-            TokenType::Plus => {
-                if let (TokenType::Number(l), TokenType::Number(r)) = (left.clone(), right.clone())
-                {
-                    Ok(TokenType::Number(l + r))
-                } else if let (TokenType::String(l), TokenType::String(r)) =
-                    (left.clone(), right.clone())
-                {
-                    Ok(TokenType::String(l + &r))
-                } else {
-                    Err(InterpreterError::RuntimeError(
-                        "Cant add these stuff".to_string(),
-                        operator.line,
-                    ))
+            TokenType::Plus => match (left.0, right.0) {
+                (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
+                (Value::String(l), Value::String(r)) => {
+                    Ok(Value::String(format!("{}{}", l, r).into()))
                 }
-            }
+                _ => Err(InterpreterError::RuntimeError(
+                    "Operands must be two numbers or two strings.".to_string(),
+                    operator.span,
+                )),
+            },
             TokenType::Greater => {
                 let (nl, nr) = Interpreter::get_numbers(left, right)?;
-                Ok((nl > nr).try_into().unwrap())
+                Ok(Value::Bool(nl > nr))
             }
             TokenType::GreaterEqual => {
                 let (nl, nr) = Interpreter::get_numbers(left, right)?;
-                Ok((nl >= nr).try_into().unwrap())
+                Ok(Value::Bool(nl >= nr))
             }
             TokenType::Less => {
                 let (nl, nr) = Interpreter::get_numbers(left, right)?;
-                Ok((nl < nr).try_into().unwrap())
+                Ok(Value::Bool(nl < nr))
             }
             TokenType::LessEqual => {
                 let (nl, nr) = Interpreter::get_numbers(left, right)?;
-                Ok((nl <= nr).try_into().unwrap())
+                Ok(Value::Bool(nl <= nr))
+            }
+            TokenType::BangEqual => Ok(Value::Bool(left.0 != right.0)),
+            TokenType::EqualEqual => Ok(Value::Bool(left.0 == right.0)),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_call_expr(&mut self, expr: &Expr) -> InterpreterResult {
+        match expr {
+            Expr::Call {
+                callee,
+                arguments,
+                span,
+                ..
+            } => {
+                let callee_span = callee.span();
+                let callee = self.evaluate(callee)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let function: &dyn LoxCallable = match &callee {
+                    Value::Native(native) => native.as_ref(),
+                    Value::Function(function) => function.as_ref(),
+                    Value::Class(class) => class,
+                    _ => {
+                        return Err(InterpreterError::RuntimeError(
+                            "Can only call functions and classes.".to_string(),
+                            callee_span,
+                        ))
+                    }
+                };
+                if arguments.len() != function.arity() {
+                    return Err(InterpreterError::RuntimeError(
+                        format!(
+                            "Expected {} arguments but got {}.",
+                            function.arity(),
+                            arguments.len()
+                        ),
+                        *span,
+                    ));
+                }
+                if self.depth >= FRAMES_MAX {
+                    return Err(InterpreterError::RuntimeError(
+                        "Stack overflow.".to_string(),
+                        *span,
+                    ));
+                }
+                self.depth += 1;
+                // Every Lox call recurses on the native stack, which is moved
+                // to the heap when it runs low, so that any thread reaches
+                // the depth limit rather than overflowing.
+                let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || {
+                    function.call(self, arguments, *span)
+                });
+                self.depth -= 1;
+                result
             }
-            TokenType::BangEqual => Ok((left != right).try_into().unwrap()),
-            TokenType::EqualEqual => Ok((left == right).try_into().unwrap()),
             _ => unsafe { unreachable_unchecked() },
         }
     }
     fn visit_grouping_expr(&mut self, expr: &Expr) -> InterpreterResult {
         match expr {
-            Expr::Grouping { expression } => self.evaluate(expression),
+            Expr::Grouping { expression, .. } => self.evaluate(expression),
             _ => unsafe { unreachable_unchecked() },
         }
     }
     fn visit_literal_expr(&mut self, expr: &crate::ast::Expr) -> InterpreterResult {
         match expr {
-            Expr::Literal { value } => Ok(value.token_type.clone()),
+            Expr::Literal { value, .. } => Ok(Value::from_literal(&value.token_type)
+                .expect("literal expressions only hold literal tokens")),
             _ => unsafe { unreachable_unchecked() },
         }
     }
     fn visit_unary_expr(&mut self, expr: &Expr) -> InterpreterResult {
         match expr {
-            Expr::Unary { operator, right } => {
-                let right = self.evaluate(right)?;
+            Expr::Unary {
+                operator, right, ..
+            } => {
+                let value = self.evaluate(right)?;
                 match operator.token_type {
                     TokenType::Minus => {
                         let num = Interpreter::get_number(
-                            right,
+                            value,
                             "Operand must be a number.".to_string(),
+                            right.span(),
                         )?;
-                        Ok(TokenType::Number(-num))
+                        Ok(Value::Number(-num))
                     }
-                    TokenType::Bang => Ok((!TryInto::<bool>::try_into(right).unwrap())
-                        .try_into()
-                        .unwrap()),
+                    TokenType::Bang => Ok(Value::Bool(!value.is_truthy())),
                     _ => unsafe { unreachable_unchecked() },
                 }
             }
@@ -218,52 +443,69 @@ impl ExprVisitor<Result<TokenType, InterpreterError>> for Interpreter {
         }
     }
 
-    fn visit_var_expr(&mut self, expr: &Expr) -> Result<TokenType, InterpreterError> {
+    fn visit_var_expr(&mut self, expr: &Expr) -> InterpreterResult {
         match expr {
-            Expr::Var { name } => match self.environment.get(name.to_string().as_str()) {
-                Some(v) => Ok(v.clone()),
-                None => Err(InterpreterError::RuntimeError(
-                    "Undefined variable".to_string(),
-                    0,
-                )),
-            },
+            Expr::Var { name, binding, .. } => {
+                let value = match binding.get() {
+                    Some(Binding::Local { depth, slot }) => self.environment.get_at(depth, slot),
+                    Some(Binding::Global(index)) => self.globals.get(index),
+                    // Unresolved code can still reach globals by name.
+                    None => self
+                        .globals
+                        .index_of(&name.lexeme())
+                        .and_then(|index| self.globals.get(index)),
+                };
+                value.ok_or_else(|| Interpreter::undefined_variable(name))
+            }
             _ => unsafe { unreachable_unchecked() },
         }
     }
 
-    fn visit_assign_expr(&mut self, expr: &Expr) -> Result<TokenType, InterpreterError> {
+    fn visit_assign_expr(&mut self, expr: &Expr) -> InterpreterResult {
         match expr {
-            Expr::Assign { name, value } => {
+            Expr::Assign {
+                name,
+                value,
+                binding,
+                ..
+            } => {
                 let value = self.evaluate(value)?;
-                self.environment
-                    .assign(name.to_string().as_str(), value.clone())
-                    .ok_or_else(|| {
-                        InterpreterError::RuntimeError("Undefined variable".to_string(), name.line)
-                    })?;
+                let assigned = match binding.get() {
+                    Some(Binding::Local { depth, slot }) => {
+                        self.environment.assign_at(depth, slot, value.clone())
+                    }
+                    Some(Binding::Global(index)) => self.globals.assign(index, value.clone()),
+                    None => self
+                        .globals
+                        .index_of(&name.lexeme())
+                        .and_then(|index| self.globals.assign(index, value.clone())),
+                };
+                assigned.ok_or_else(|| Interpreter::undefined_variable(name))?;
                 Ok(value)
             }
             _ => unsafe { unreachable_unchecked() },
         }
     }
 
-    fn visit_logical_expr(&mut self, expr: &Expr) -> Result<TokenType, InterpreterError> {
+    fn visit_logical_expr(&mut self, expr: &Expr) -> InterpreterResult {
         match expr {
             Expr::Logical {
                 left,
                 operator,
                 right,
+                ..
             } => {
                 let left = self.evaluate(left)?;
                 match operator.token_type {
                     TokenType::Or => {
-                        if TryInto::<bool>::try_into(left.clone()).unwrap() {
+                        if left.is_truthy() {
                             Ok(left)
                         } else {
                             self.evaluate(right)
                         }
                     }
                     TokenType::And => {
-                        if !TryInto::<bool>::try_into(left.clone()).unwrap() {
+                        if !left.is_truthy() {
                             Ok(left)
                         } else {
                             self.evaluate(right)
@@ -275,4 +517,104 @@ impl ExprVisitor<Result<TokenType, InterpreterError>> for Interpreter {
             _ => unsafe { unreachable_unchecked() },
         }
     }
+
+    fn visit_get_expr(&mut self, expr: &Expr) -> InterpreterResult {
+        match expr {
+            Expr::Get { object, name, .. } => {
+                let object = self.evaluate(object)?;
+                let instance = match &object {
+                    Value::Instance(instance) => instance,
+                    _ => {
+                        return Err(InterpreterError::RuntimeError(
+                            "Only instances have properties.".to_string(),
+                            name.span,
+                        ))
+                    }
+                };
+                if let Some(value) = instance.borrow().get_field(&name.lexeme()) {
+                    return Ok(value);
+                }
+                let class = instance.borrow().class().clone();
+                Interpreter::bind_method(&class, object, name)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+
+    fn visit_set_expr(&mut self, expr: &Expr) -> InterpreterResult {
+        match expr {
+            Expr::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                let instance = match self.evaluate(object)? {
+                    Value::Instance(instance) => instance,
+                    _ => {
+                        return Err(InterpreterError::RuntimeError(
+                            "Only instances have fields.".to_string(),
+                            name.span,
+                        ))
+                    }
+                };
+                let value = self.evaluate(value)?;
+                instance
+                    .borrow_mut()
+                    .set_field(name.lexeme(), value.clone());
+                Ok(value)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+
+    fn visit_super_expr(&mut self, expr: &Expr) -> InterpreterResult {
+        match expr {
+            Expr::Super {
+                keyword,
+                method,
+                binding,
+                ..
+            } => {
+                let depth = match binding.get() {
+                    Some(Binding::Local { depth, .. }) => depth,
+                    _ => 0,
+                };
+                let superclass = match self.environment.get_at(depth, 0) {
+                    Some(Value::Class(class)) => class,
+                    _ => {
+                        return Err(InterpreterError::RuntimeError(
+                            "Can't use 'super' in a class with no superclass.".to_string(),
+                            keyword.span,
+                        ))
+                    }
+                };
+                // `this` lives in the scope just inside the one holding `super`.
+                let instance = depth
+                    .checked_sub(1)
+                    .and_then(|depth| self.environment.get_at(depth, 0))
+                    .unwrap_or(Value::Nil);
+                Interpreter::bind_method(&superclass, instance, method)
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+
+    fn visit_this_expr(&mut self, expr: &Expr) -> InterpreterResult {
+        match expr {
+            Expr::This {
+                keyword, binding, ..
+            } => match binding.get() {
+                Some(Binding::Local { depth, slot }) => self.environment.get_at(depth, slot),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                InterpreterError::RuntimeError(
+                    "Can't use 'this' outside of a class.".to_string(),
+                    keyword.span,
+                )
+            }),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
 }
@@ -1,21 +1,43 @@
+use clap::ValueEnum;
+use diagnostics::{ColorChoice, Diagnostic, Renderer};
+use dump::DumpFormat;
+use environment::Globals;
 use interpreter::Interpreter;
-use rustyline::DefaultEditor;
-use scanner::Scanner;
-use std::path::PathBuf;
+use scanner::{Dialect, Scanner};
+use std::path::{Path, PathBuf};
 use thiserror::Error;
+use vm::Vm;
 
 pub mod ast;
+pub mod chunk;
+pub mod class;
 pub mod cli;
+pub mod compiler;
+pub mod diagnostics;
+pub mod dump;
 pub mod environment;
+pub mod formatter;
+pub mod function;
 pub mod interpreter;
+pub mod linter;
+pub mod native;
+pub mod optimizer;
 pub mod parser;
+pub mod repl;
+pub mod resolver;
 pub mod scanner;
 pub mod tokens;
+pub mod value;
+pub mod vm;
 #[derive(Error, Debug)]
 pub enum LoxError {
     #[error("{0}")]
     ParseError(#[from] parser::ParseError),
     #[error("{0}")]
+    ResolveError(#[from] resolver::ResolveError),
+    #[error("{0}")]
+    CompileError(#[from] compiler::CompileError),
+    #[error("{0}")]
     InterpreterError(#[from] interpreter::InterpreterError),
     #[error("{0}")]
     ScanError(#[from] scanner::ScanError),
@@ -23,49 +45,353 @@ pub enum LoxError {
     IoError(#[from] std::io::Error),
     #[error("{0}")]
     ReadlineError(#[from] rustyline::error::ReadlineError),
+    /// A file `fmt --check` would change.
+    #[error("{} is not formatted.", .0.display())]
+    Unformatted(PathBuf),
+    /// `check` found problems, which it has already reported.
+    #[error("{} warning{} emitted.", .0, if *.0 == 1 { "" } else { "s" })]
+    Warnings(usize),
+    /// Several errors found in one pass, e.g. every syntax error in a file.
+    #[error("{}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))]
+    Multiple(Vec<LoxError>),
+}
+impl LoxError {
+    /// The status to exit with, following the BSD `sysexits.h` codes used by
+    /// the reference implementation: 65 for code that can't be compiled, 70
+    /// for a runtime error and 74 for failed reads and writes. Files that
+    /// need formatting or have warnings exit with 1.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            LoxError::ScanError(_)
+            | LoxError::ParseError(_)
+            | LoxError::ResolveError(_)
+            | LoxError::CompileError(_) => 65,
+            LoxError::InterpreterError(_) => 70,
+            LoxError::IoError(_) | LoxError::ReadlineError(_) => 74,
+            LoxError::Unformatted(_) | LoxError::Warnings(_) => 1,
+            LoxError::Multiple(errors) => errors.iter().map(LoxError::exit_code).max().unwrap_or(1),
+        }
+    }
+}
+impl From<Vec<LoxError>> for LoxError {
+    fn from(mut errors: Vec<LoxError>) -> Self {
+        if errors.len() == 1 {
+            errors.remove(0)
+        } else {
+            LoxError::Multiple(errors)
+        }
+    }
+}
+/// Combines the errors of the scanner and the parser, in source order.
+fn syntax_errors(
+    scan_errors: Vec<scanner::ScanError>,
+    parse_errors: Vec<parser::ParseError>,
+) -> Option<LoxError> {
+    let mut errors: Vec<LoxError> = scan_errors
+        .into_iter()
+        .map(LoxError::from)
+        .chain(parse_errors.into_iter().map(LoxError::from))
+        .collect();
+    if errors.is_empty() {
+        return None;
+    }
+    errors.sort_by_key(|e| Diagnostic::from(e).span.map(|span| span.start));
+    Some(errors.into())
+}
+/// Resolves variable bindings in `statements` against the globals they will
+/// run with, returning any static errors.
+fn resolve(statements: &[ast::Stmt], globals: &mut Globals) -> Option<LoxError> {
+    let errors = resolver::Resolver::new(globals).resolve(statements);
+    if errors.is_empty() {
+        return None;
+    }
+    Some(
+        errors
+            .into_iter()
+            .map(LoxError::from)
+            .collect::<Vec<_>>()
+            .into(),
+    )
 }
 
-pub fn run_file(file: PathBuf) -> Result<(), LoxError> {
-    // Read the file and run
-    let contents = std::fs::read_to_string(file)?;
-    run(contents, None).map(|_| ())
-}
-pub fn run_prompt() -> Result<(), LoxError> {
-    //println!("{:?}", stmt);
-    let mut interpreter = interpreter::Interpreter::new();
-    let mut rl = DefaultEditor::new()?;
-    loop {
-        let readline = rl.readline(">> ");
-        let line = readline?;
-        rl.add_history_entry(line.as_str())?;
-        let mut scanner = Scanner::new(line.as_str());
-        let tokens = scanner.scan_tokens()?;
-        let mut parser = parser::Parser::new(tokens);
-        let stmt = parser.parse()?;
-        println!("{:?}", stmt);
-        match interpreter.interpret(stmt) {
-            Ok(_) => (),
-            Err(e) => {
-                eprintln!("{}", e);
+/// Which engine runs the code.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Walk the syntax tree directly.
+    #[default]
+    Tree,
+    /// Compile to bytecode and run it on a stack machine.
+    Vm,
+}
+
+/// Settings shared by every way of running Lox code.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Options {
+    pub dialect: Dialect,
+    pub color: ColorChoice,
+    pub backend: Backend,
+    /// Simplify programs before running them.
+    pub optimize: bool,
+}
+
+/// The state of a program across runs, for either backend.
+enum Runtime {
+    Tree(Interpreter),
+    Vm(Vm),
+}
+impl Runtime {
+    fn new(backend: Backend) -> Self {
+        match backend {
+            Backend::Tree => Runtime::Tree(Interpreter::new()),
+            Backend::Vm => Runtime::Vm(Vm::new()),
+        }
+    }
+    fn globals(&self) -> &Globals {
+        match self {
+            Runtime::Tree(interpreter) => interpreter.globals(),
+            Runtime::Vm(vm) => vm.globals(),
+        }
+    }
+    fn globals_mut(&mut self) -> &mut Globals {
+        match self {
+            Runtime::Tree(interpreter) => interpreter.globals_mut(),
+            Runtime::Vm(vm) => vm.globals_mut(),
+        }
+    }
+    /// Runs resolved statements, optimizing them first if asked to.
+    fn execute(&mut self, statements: Vec<ast::Stmt>, options: Options) -> Result<(), LoxError> {
+        let statements = if options.optimize {
+            optimizer::optimize(statements)
+        } else {
+            statements
+        };
+        match self {
+            Runtime::Tree(interpreter) => {
+                interpreter.interpret(statements)?;
+            }
+            Runtime::Vm(vm) => {
+                let chunk = compiler::Compiler::new(vm.globals_mut()).compile(&statements)?;
+                vm.interpret(chunk)?;
             }
         }
+        Ok(())
     }
 }
-fn run(source: String, interpreter: Option<Interpreter>) -> Result<Interpreter, LoxError> {
-    let mut scanner = Scanner::new(source.as_str());
-    let tokens = scanner.scan_tokens()?;
-    let mut parser = parser::Parser::new(tokens);
-    let stmt = parser.parse()?;
-    //println!("{:?}", stmt);
-    let mut interpreter = match interpreter {
-        Some(i) => i,
-        None => interpreter::Interpreter::new(),
-    };
-    match interpreter.interpret(stmt) {
-        Ok(_) => Ok(interpreter),
-        Err(e) => {
-            eprintln!("{}", e);
-            Ok(interpreter)
+
+/// Everything run on one runtime, in order, so that an error can be shown
+/// against the input it comes from even when code from an earlier input
+/// raises it. Each input is scanned at its offset in `text`, so spans point
+/// into the whole.
+#[derive(Default)]
+struct Sources {
+    text: String,
+    /// Where each input starts in `text`, and the name it is reported under.
+    files: Vec<(usize, String)>,
+}
+impl Sources {
+    /// Appends `source`, returning the offset it starts at.
+    fn add(&mut self, file_name: &str, source: &str) -> usize {
+        let start = self.text.len();
+        self.text.push_str(source);
+        self.files.push((start, file_name.to_string()));
+        start
+    }
+    /// The name of the input that `offset` falls in.
+    fn file_name(&self, offset: usize) -> &str {
+        let index = self.files.partition_point(|(start, _)| *start <= offset);
+        self.files
+            .get(index.saturating_sub(1))
+            .map_or("", |(_, name)| name)
+    }
+    /// Renders `error` against the input each of its errors comes from.
+    fn render(&self, error: &LoxError, color: bool) -> String {
+        match error {
+            LoxError::Multiple(errors) => errors.iter().map(|e| self.render(e, color)).collect(),
+            _ => {
+                let offset = Diagnostic::from(error).span.map_or(0, |span| span.start);
+                Renderer::new(self.file_name(offset), &self.text)
+                    .color(color)
+                    .render_error(error)
+            }
         }
     }
 }
+
+/// Runs the script in `file`. Every entry point here reports its errors on
+/// stderr before returning them.
+pub fn run_file(file: PathBuf, options: Options) -> Result<(), LoxError> {
+    let contents = read_source(&file, options)?;
+    let mut runtime = Runtime::new(options.backend);
+    run(
+        &mut Sources::default(),
+        &contents,
+        &file.display().to_string(),
+        &mut runtime,
+        options,
+        false,
+    )
+}
+/// Prints the tokens of `file`, then reports any lexical errors.
+pub fn dump_tokens(file: PathBuf, format: DumpFormat, options: Options) -> Result<(), LoxError> {
+    let source = read_source(&file, options)?;
+    let (tokens, errors) = Scanner::with_dialect(source.as_str(), options.dialect).scan_tokens();
+    print!("{}", dump::tokens(&tokens, format));
+    match syntax_errors(errors, Vec::new()) {
+        Some(e) => Err(report(&file, &source, options, e)),
+        None => Ok(()),
+    }
+}
+/// Prints the syntax tree of `file`, unless it has syntax errors.
+pub fn dump_ast(file: PathBuf, format: DumpFormat, options: Options) -> Result<(), LoxError> {
+    let source = read_source(&file, options)?;
+    let (tokens, scan_errors) =
+        Scanner::with_dialect(source.as_str(), options.dialect).scan_tokens();
+    let (stmt, parse_errors) = parser::Parser::new(tokens).parse();
+    if let Some(e) = syntax_errors(scan_errors, parse_errors) {
+        return Err(report(&file, &source, options, e));
+    }
+    print!("{}", dump::ast(&stmt, format));
+    Ok(())
+}
+/// Formats each of `files`, printing the result unless `write` asks for the
+/// files to be rewritten in place or `check` only for the ones that would
+/// change to be reported.
+pub fn format_files(
+    files: Vec<PathBuf>,
+    check: bool,
+    write: bool,
+    options: Options,
+) -> Result<(), LoxError> {
+    let mut errors = Vec::new();
+    for file in files {
+        let source = read_source(&file, options)?;
+        let mut scanner = Scanner::with_dialect(source.as_str(), options.dialect);
+        let (tokens, scan_errors) = scanner.scan_tokens();
+        let (stmt, parse_errors) = parser::Parser::new(tokens).parse();
+        if let Some(e) = syntax_errors(scan_errors, parse_errors) {
+            errors.push(report(&file, &source, options, e));
+            continue;
+        }
+        let formatted =
+            formatter::Formatter::new(&source, &scanner.comments, options.dialect).format(&stmt);
+        if check {
+            if formatted != source {
+                errors.push(report(
+                    &file,
+                    &source,
+                    options,
+                    LoxError::Unformatted(file.clone()),
+                ));
+            }
+        } else if write {
+            if formatted != source {
+                std::fs::write(&file, formatted)
+                    .map_err(|e| io_error("write", &file, options, e))?;
+            }
+        } else {
+            print!("{}", formatted);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+/// Reports syntax and resolution errors in each of `files`, then warnings
+/// for likely mistakes, leaving out the lints in `allow`.
+pub fn check_files(
+    files: Vec<PathBuf>,
+    allow: Vec<linter::Lint>,
+    options: Options,
+) -> Result<(), LoxError> {
+    let mut errors = Vec::new();
+    let mut warnings = 0;
+    for file in files {
+        let source = read_source(&file, options)?;
+        let mut scanner = Scanner::with_dialect(source.as_str(), options.dialect);
+        let (tokens, scan_errors) = scanner.scan_tokens();
+        let (stmt, parse_errors) = parser::Parser::new(tokens).parse();
+        if let Some(e) = syntax_errors(scan_errors, parse_errors)
+            .or_else(|| resolve(&stmt, &mut Globals::default()))
+        {
+            errors.push(report(&file, &source, options, e));
+            continue;
+        }
+        let file_name = file.display().to_string();
+        let renderer =
+            Renderer::new(&file_name, &source).color(options.color.enabled(std::io::stderr()));
+        for warning in linter::lint(&stmt, &source, &scanner.comments, &allow) {
+            eprint!("{}", renderer.render(&(&warning).into()));
+            warnings += 1;
+        }
+    }
+    if warnings > 0 {
+        errors.push(report(
+            Path::new(""),
+            "",
+            options,
+            LoxError::Warnings(warnings),
+        ));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+/// Reads `file`, reporting it if that fails.
+fn read_source(file: &Path, options: Options) -> Result<String, LoxError> {
+    std::fs::read_to_string(file).map_err(|e| io_error("read", file, options, e))
+}
+/// Reports that `file` could not be read or written.
+fn io_error(action: &str, file: &Path, options: Options, error: std::io::Error) -> LoxError {
+    let message = format!("Can't {} {}: {}.", action, file.display(), error);
+    let renderer = Renderer::new("", "").color(options.color.enabled(std::io::stderr()));
+    eprint!("{}", renderer.render(&Diagnostic::error(message)));
+    error.into()
+}
+/// Renders `error` against the source of `file` on stderr and hands it back.
+fn report(file: &Path, source: &str, options: Options, error: LoxError) -> LoxError {
+    let file_name = file.display().to_string();
+    let renderer =
+        Renderer::new(&file_name, source).color(options.color.enabled(std::io::stderr()));
+    eprint!("{}", renderer.render_error(&error));
+    error
+}
+/// Runs `source` on `runtime`, reporting any error. The runtime is still
+/// usable after a runtime error. `sources` holds everything run on it
+/// before, and gets `source` added. With `echo`, top-level expression
+/// statements print their value.
+fn run(
+    sources: &mut Sources,
+    source: &str,
+    file_name: &str,
+    runtime: &mut Runtime,
+    options: Options,
+    echo: bool,
+) -> Result<(), LoxError> {
+    let start = sources.add(file_name, source);
+    let sources = &*sources;
+    let report = |e: LoxError| {
+        eprint!(
+            "{}",
+            sources.render(&e, options.color.enabled(std::io::stderr()))
+        );
+        e
+    };
+    let (tokens, scan_errors) = Scanner::with_dialect(&sources.text, options.dialect)
+        .starting_at(start)
+        .scan_tokens();
+    let mut parser = parser::Parser::new(tokens);
+    let (stmt, parse_errors) = parser.parse();
+    if let Some(e) = syntax_errors(scan_errors, parse_errors) {
+        return Err(report(e));
+    }
+    // Echoing adds code, which has to be resolved with the rest.
+    let stmt = if echo { repl::echo(stmt) } else { stmt };
+    if let Some(e) = resolve(&stmt, runtime.globals_mut()) {
+        return Err(report(e));
+    }
+    runtime.execute(stmt, options).map_err(report)
+}
@@ -0,0 +1,588 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hint::unreachable_unchecked,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    ast::{AstPrinter, Expr, ExprVisitor, Stmt, StmtVisitor},
+    diagnostics::Diagnostic,
+    native::standard_library,
+    tokens::{Comment, Span, Token, TokenType},
+};
+
+/// The mistakes `lox check` looks for. Each has a stable code, and either
+/// the code or the name can be used to allow it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Lint {
+    /// A local variable that is never read.
+    #[value(alias = "L001")]
+    UnusedVariable,
+    /// A local declaration hiding one in an enclosing scope.
+    #[value(alias = "L002")]
+    Shadowing,
+    /// An assignment to a global that is never declared.
+    #[value(alias = "L003")]
+    UndeclaredAssignment,
+    /// Statements after a `return`.
+    #[value(alias = "L004")]
+    UnreachableCode,
+    /// An `if` or `while` condition that never changes.
+    #[value(alias = "L005")]
+    ConstantCondition,
+    /// A comparison of an expression with itself.
+    #[value(alias = "L006")]
+    SelfComparison,
+}
+impl Lint {
+    pub fn code(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "L001",
+            Lint::Shadowing => "L002",
+            Lint::UndeclaredAssignment => "L003",
+            Lint::UnreachableCode => "L004",
+            Lint::ConstantCondition => "L005",
+            Lint::SelfComparison => "L006",
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::Shadowing => "shadowing",
+            Lint::UndeclaredAssignment => "undeclared-assignment",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::SelfComparison => "self-comparison",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub message: String,
+    pub span: Span,
+}
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Diagnostic::warning(&warning.message)
+            .with_code(warning.lint.code())
+            .with_span(warning.span)
+            .with_help(format!(
+                "add `// lox-allow: {}` to silence this",
+                warning.lint.name()
+            ))
+    }
+}
+
+/// Comments starting with this, followed by a comma separated list of lint
+/// names or codes, allow those lints on their line, or on the next line if
+/// the comment is alone on its own.
+const ALLOW: &str = "lox-allow:";
+
+/// Where a lint is allowed by a comment.
+fn allowed_lines(source: &str, comments: &[Comment]) -> HashSet<(usize, Lint)> {
+    let mut allowed = HashSet::new();
+    for comment in comments {
+        let Some(lints) = comment
+            .text
+            .strip_prefix("//")
+            .and_then(|text| text.trim_start().strip_prefix(ALLOW))
+        else {
+            continue;
+        };
+        let line_start = source[..comment.span.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line = if source[line_start..comment.span.start].trim().is_empty() {
+            comment.span.line + 1
+        } else {
+            comment.span.line
+        };
+        for lint in lints.split(',') {
+            if let Ok(lint) = Lint::from_str(lint.trim(), true) {
+                allowed.insert((line, lint));
+            }
+        }
+    }
+    allowed
+}
+
+/// Checks `statements` for likely mistakes, leaving out lints that are in
+/// `allow` or allowed by a comment. Warnings come back in source order.
+pub fn lint(
+    statements: &[Stmt],
+    source: &str,
+    comments: &[Comment],
+    allow: &[Lint],
+) -> Vec<Warning> {
+    let allowed = allowed_lines(source, comments);
+    let mut linter = Linter::new(statements);
+    linter.statements(statements);
+    let mut warnings: Vec<_> = linter
+        .warnings
+        .into_iter()
+        .filter(|w| !allow.contains(&w.lint) && !allowed.contains(&(w.span.line, w.lint)))
+        .collect();
+    warnings.sort_by_key(|w| w.span.start);
+    warnings
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LocalKind {
+    Variable,
+    Parameter,
+    /// A function or class, which are not reported when unused.
+    Declaration,
+}
+struct Local {
+    kind: LocalKind,
+    span: Span,
+    used: bool,
+}
+
+/// Walks the program with its own view of the scopes. The resolver's
+/// bindings say what each use refers to, but not whether a declaration is
+/// ever used, or what it shadows. Only code that resolves is linted.
+struct Linter {
+    scopes: Vec<HashMap<String, Local>>,
+    /// Every global the top level declares, wherever it is used from, and
+    /// the natives.
+    globals: HashSet<String>,
+    warnings: Vec<Warning>,
+}
+impl Linter {
+    fn new(statements: &[Stmt]) -> Self {
+        let globals = statements
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Stmt::Var { name, .. } | Stmt::Function { name, .. } | Stmt::Class { name, .. } => {
+                    Some(name.lexeme())
+                }
+                _ => None,
+            })
+            .chain(
+                standard_library()
+                    .iter()
+                    .map(|native| native.name().to_string()),
+            )
+            .collect();
+        Self {
+            scopes: Vec::new(),
+            globals,
+            warnings: Vec::new(),
+        }
+    }
+    fn warn(&mut self, lint: Lint, span: Span, message: String) {
+        self.warnings.push(Warning {
+            lint,
+            message,
+            span,
+        });
+    }
+    /// Visits a list of statements, warning once about whatever follows a
+    /// statement that always returns.
+    fn statements(&mut self, statements: &[Stmt]) {
+        let mut returned = false;
+        let mut reported = false;
+        for stmt in statements {
+            if returned && !reported {
+                self.warn(
+                    Lint::UnreachableCode,
+                    stmt.span(),
+                    "Unreachable code.".to_string(),
+                );
+                reported = true;
+            }
+            stmt.accept(self);
+            returned |= always_returns(stmt);
+        }
+    }
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, local) in scope {
+            if local.kind == LocalKind::Variable && !local.used && !name.starts_with('_') {
+                self.warn(
+                    Lint::UnusedVariable,
+                    local.span,
+                    format!("Local variable '{}' is never used.", name),
+                );
+            }
+        }
+    }
+    fn declare(&mut self, name: &Token, kind: LocalKind) {
+        let lexeme = name.lexeme();
+        let Some((scope, enclosing)) = self.scopes.split_last_mut() else {
+            return;
+        };
+        if enclosing.iter().any(|scope| scope.contains_key(&lexeme)) {
+            self.warnings.push(Warning {
+                lint: Lint::Shadowing,
+                message: format!("'{}' shadows a variable in an enclosing scope.", lexeme),
+                span: name.span,
+            });
+        }
+        scope.insert(
+            lexeme,
+            Local {
+                kind,
+                span: name.span,
+                used: false,
+            },
+        );
+    }
+    fn local(&mut self, name: &str) -> Option<&mut Local> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+    fn function(&mut self, params: &[Token], body: &[Stmt]) {
+        self.begin_scope();
+        for param in params {
+            self.declare(param, LocalKind::Parameter);
+        }
+        self.statements(body);
+        self.end_scope();
+    }
+    fn condition(&mut self, condition: &Expr) {
+        condition.accept(self);
+        if is_constant(condition) {
+            self.warn(
+                Lint::ConstantCondition,
+                condition.span(),
+                "This condition is always the same.".to_string(),
+            );
+        }
+    }
+}
+
+/// Whether running `stmt` always ends in a `return`.
+fn always_returns(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return { .. } => true,
+        Stmt::Block { statements, .. } => statements.iter().any(always_returns),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => always_returns(then_branch) && always_returns(else_branch),
+        _ => false,
+    }
+}
+
+/// Whether `expr` is made of literals only.
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal { .. } => true,
+        Expr::Grouping { expression, .. } => is_constant(expression),
+        Expr::Unary { right, .. } => is_constant(right),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            is_constant(left) && is_constant(right)
+        }
+        _ => false,
+    }
+}
+
+/// Whether evaluating `expr` twice gives the same result, as far as can be
+/// told without running it.
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call { .. } | Expr::Assign { .. } | Expr::Set { .. } => false,
+        Expr::Grouping { expression, .. } => is_pure(expression),
+        Expr::Unary { right, .. } => is_pure(right),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            is_pure(left) && is_pure(right)
+        }
+        Expr::Get { object, .. } => is_pure(object),
+        Expr::Literal { .. } | Expr::Var { .. } | Expr::This { .. } | Expr::Super { .. } => true,
+    }
+}
+
+impl StmtVisitor<()> for Linter {
+    fn visit_print_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print { expression, .. } => expression.accept(self),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_return_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    value.accept(self);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_function_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Function {
+                name, params, body, ..
+            } => {
+                self.declare(name, LocalKind::Declaration);
+                self.function(params, body);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_if_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.condition(condition);
+                then_branch.accept(self);
+                if let Some(else_branch) = else_branch {
+                    else_branch.accept(self);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_expr_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression, .. } => expression.accept(self),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                initializer.accept(self);
+                self.declare(name, LocalKind::Variable);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_block_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                self.begin_scope();
+                self.statements(statements);
+                self.end_scope();
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_while_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::While {
+                condition, body, ..
+            } => {
+                // `while (true)` and `for (;;)` loop on purpose.
+                match condition {
+                    Expr::Literal { value, .. } if value.token_type == TokenType::True => {}
+                    condition => self.condition(condition),
+                }
+                body.accept(self);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_class_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                self.declare(name, LocalKind::Declaration);
+                if let Some(superclass) = superclass {
+                    superclass.accept(self);
+                }
+                for method in methods {
+                    if let Stmt::Function { params, body, .. } = method {
+                        self.function(params, body);
+                    }
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
+impl ExprVisitor<()> for Linter {
+    fn visit_binary_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                left.accept(self);
+                right.accept(self);
+                let comparison = matches!(
+                    operator.token_type,
+                    TokenType::EqualEqual
+                        | TokenType::BangEqual
+                        | TokenType::Less
+                        | TokenType::LessEqual
+                        | TokenType::Greater
+                        | TokenType::GreaterEqual
+                );
+                let mut printer = AstPrinter::default();
+                // Comparing literals is already a constant condition.
+                if comparison
+                    && is_pure(left)
+                    && !is_constant(left)
+                    && printer.print(left) == printer.print(right)
+                {
+                    self.warn(
+                        Lint::SelfComparison,
+                        expr.span(),
+                        format!("Both sides of '{}' are the same.", operator.lexeme()),
+                    );
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_call_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                callee.accept(self);
+                for argument in arguments {
+                    argument.accept(self);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_grouping_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Grouping { expression, .. } => expression.accept(self),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_literal_expr(&mut self, _expr: &Expr) {}
+    fn visit_unary_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Unary { right, .. } => right.accept(self),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Var { name, .. } => {
+                if let Some(local) = self.local(&name.lexeme()) {
+                    local.used = true;
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_assign_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign { name, value, .. } => {
+                value.accept(self);
+                let lexeme = name.lexeme();
+                // Assigning a local does not count as using it.
+                if self.local(&lexeme).is_none() && !self.globals.contains(&lexeme) {
+                    self.warn(
+                        Lint::UndeclaredAssignment,
+                        name.span,
+                        format!("Assignment to undeclared variable '{}'.", lexeme),
+                    );
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_logical_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Logical { left, right, .. } => {
+                left.accept(self);
+                right.accept(self);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_get_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Get { object, .. } => object.accept(self),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_set_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Set { object, value, .. } => {
+                object.accept(self);
+                value.accept(self);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_super_expr(&mut self, _expr: &Expr) {}
+    fn visit_this_expr(&mut self, _expr: &Expr) {}
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::parse_source, scanner::Dialect};
+    fn warnings(source: &str, allow: &[Lint]) -> Vec<(Lint, usize)> {
+        let (stmts, comments) = parse_source(source, Dialect::Lox);
+        lint(&stmts, source, &comments, allow)
+            .into_iter()
+            .map(|w| (w.lint, w.span.line))
+            .collect()
+    }
+    #[test]
+    fn finds_each_lint() {
+        let source = "var g;\n\
+                      fun f(a) {\n\
+                        var unused = 1;\n\
+                        { var a = 2; print a; }\n\
+                        g = 1;\n\
+                        h = 2;\n\
+                        return a;\n\
+                        print \"never\";\n\
+                      }\n\
+                      if (1 < 2) print g;\n\
+                      print g == g;\n\
+                      print f(1) == f(1);\n";
+        assert_eq!(
+            warnings(source, &[]),
+            vec![
+                (Lint::UnusedVariable, 3),
+                (Lint::Shadowing, 4),
+                (Lint::UndeclaredAssignment, 6),
+                (Lint::UnreachableCode, 8),
+                (Lint::ConstantCondition, 10),
+                (Lint::SelfComparison, 11),
+            ]
+        );
+    }
+    #[test]
+    fn idiomatic_code_is_quiet() {
+        let source = "fun f(n) {\n\
+                        for (var i = 0; i < n; i = i + 1) print i;\n\
+                        while (true) { if (n > 1) return n; else return 0; }\n\
+                        for (;;) {}\n\
+                        var _ignored = 1;\n\
+                      }\n\
+                      class A { init(x) { this.x = x; } }\n";
+        assert_eq!(warnings(source, &[]), vec![]);
+    }
+    #[test]
+    fn lints_can_be_allowed() {
+        let source = "{\n  var a = 1; var b; // lox-allow: unused-variable\n  // lox-allow: L002, L005\n  { var a; if (nil) print a; }\n  if (false) print a;\n}\n";
+        assert_eq!(warnings(source, &[]), vec![(Lint::ConstantCondition, 5)]);
+        assert_eq!(warnings(source, &[Lint::ConstantCondition]), vec![]);
+    }
+}
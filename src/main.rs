@@ -1,12 +1,16 @@
 use clap::Parser;
 use log::*;
 use lox_rs_ast::{
+    check_files,
     cli::{Cli, Commands},
-    run_file, run_prompt, LoxError,
+    dump_ast, dump_tokens, format_files,
+    repl::run_prompt,
+    run_file,
 };
 use simplelog::*;
-use std::fs::File;
-fn main() -> Result<(), LoxError> {
+use std::{fs::File, process::ExitCode};
+
+fn main() -> ExitCode {
     let args = Cli::parse();
     let config = ConfigBuilder::new()
         .set_level_color(Level::Error, Some(Color::Rgb(191, 0, 0)))
@@ -30,11 +34,31 @@ fn main() -> Result<(), LoxError> {
         ),
     ])
     .unwrap();
-    if let Some(file) = args.command {
+    let options = args.options();
+    let result = if let Some(file) = args.command {
         match file {
-            Commands::File { file } => run_file(file),
+            Commands::File { file, interactive } => {
+                if interactive {
+                    run_prompt(options, Some(&file))
+                } else {
+                    run_file(file, options)
+                }
+            }
+            Commands::Tokens { file, format } => dump_tokens(file, format, options),
+            Commands::Ast { file, format } => dump_ast(file, format, options),
+            Commands::Fmt {
+                files,
+                check,
+                write,
+            } => format_files(files, check, write, options),
+            Commands::Check { files, allow } => check_files(files, allow, options),
         }
     } else {
-        run_prompt()
+        run_prompt(options, None)
+    };
+    // Errors have already been reported on stderr.
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => ExitCode::from(e.exit_code()),
     }
 }
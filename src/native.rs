@@ -0,0 +1,99 @@
+use std::{
+    fmt::Debug,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    function::LoxCallable,
+    interpreter::{Interpreter, InterpreterError},
+    tokens::Span,
+    value::Value,
+};
+
+/// The Rust side of a native function: it gets exactly as many arguments as
+/// the function's arity, and fails with a message for a runtime error.
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A function implemented in Rust and exposed to scripts as a global.
+#[derive(Clone)]
+pub struct NativeFunction {
+    name: Rc<str>,
+    arity: usize,
+    function: Rc<NativeFn>,
+}
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            arity,
+            function: Rc::new(function),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Runs the function on arguments whose count has been checked. A
+    /// failure is only a message, to be reported at the call.
+    pub fn invoke(&self, arguments: &[Value]) -> Result<Value, String> {
+        (self.function)(arguments)
+    }
+}
+impl LoxCallable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, InterpreterError> {
+        self.invoke(&arguments)
+            .map_err(|message| InterpreterError::RuntimeError(message, span))
+    }
+}
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// The natives every program starts with.
+pub fn standard_library() -> Vec<NativeFunction> {
+    vec![NativeFunction::new("clock", 0, |_| {
+        // Seconds since the epoch, as in the reference implementation.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?;
+        Ok(Value::Number(now.as_secs_f64()))
+    })]
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn errors_point_at_the_call() {
+        let native = NativeFunction::new("fail", 1, |args| Err(format!("bad {}", args[0])));
+        let span = Span::new(3, 9, 1, 4);
+        match native.call(&mut Interpreter::new(), vec![Value::from(1.0)], span) {
+            Err(InterpreterError::RuntimeError(message, at)) => {
+                assert_eq!((message.as_str(), at), ("bad 1", span))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+    #[test]
+    fn clock_counts_seconds() {
+        let clock = &standard_library()[0];
+        assert_eq!((clock.name(), clock.arity()), ("clock", 0));
+        let Ok(Value::Number(seconds)) = clock.invoke(&[]) else {
+            panic!("clock() should return a number");
+        };
+        assert!(seconds > 1e9);
+    }
+}
@@ -0,0 +1,356 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{Expr, Stmt},
+    tokens::{Span, Token, TokenType},
+    value::Value,
+};
+
+/// Simplifies resolved statements before they run.
+///
+/// Only work whose outcome is known without running the program is removed:
+/// constant operands are folded, branches behind constant conditions are
+/// dropped and groupings are unwrapped. Anything that would fail at runtime,
+/// like `"a" - 1`, is left alone so that it still fails there. Scopes are
+/// never merged or reordered, so the resolver's bindings stay valid.
+pub fn optimize(statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements.into_iter().filter_map(simplify_stmt).collect()
+}
+
+/// Simplifies `stmt`, or returns `None` if it can never do anything.
+fn simplify_stmt(stmt: Stmt) -> Option<Stmt> {
+    Some(match stmt {
+        Stmt::Expression { expression, span } => Stmt::Expression {
+            expression: simplify_expr(expression),
+            span,
+        },
+        Stmt::Print { expression, span } => Stmt::Print {
+            expression: simplify_expr(expression),
+            span,
+        },
+        Stmt::Var {
+            name,
+            initializer,
+            span,
+        } => Stmt::Var {
+            name,
+            initializer: simplify_expr(initializer),
+            span,
+        },
+        Stmt::Return {
+            keyword,
+            value,
+            span,
+        } => Stmt::Return {
+            keyword,
+            value: value.map(simplify_expr),
+            span,
+        },
+        Stmt::Function {
+            name,
+            params,
+            body,
+            span,
+        } => Stmt::Function {
+            name,
+            params,
+            body: function_body(body),
+            span,
+        },
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+            span,
+        } => Stmt::Class {
+            name,
+            superclass: superclass.map(simplify_expr),
+            methods: optimize(methods),
+            span,
+        },
+        Stmt::Block { statements, span } => Stmt::Block {
+            statements: optimize(statements),
+            span,
+        },
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            span,
+        } => {
+            let condition = simplify_expr(condition);
+            match literal_value(&condition) {
+                Some(value) if value.is_truthy() => return simplify_stmt(*then_branch),
+                Some(_) => return else_branch.and_then(|branch| simplify_stmt(*branch)),
+                None => Stmt::If {
+                    condition,
+                    then_branch: Box::new(branch(*then_branch)),
+                    else_branch: else_branch
+                        .and_then(|branch| simplify_stmt(*branch))
+                        .map(Box::new),
+                    span,
+                },
+            }
+        }
+        Stmt::While {
+            condition,
+            body,
+            span,
+        } => {
+            let condition = simplify_expr(condition);
+            if literal_value(&condition).is_some_and(|value| !value.is_truthy()) {
+                return None;
+            }
+            Stmt::While {
+                condition,
+                body: Box::new(branch(*body)),
+                span,
+            }
+        }
+    })
+}
+
+/// Simplifies a statement that must stay in place, such as the body of a
+/// loop, leaving an empty block if nothing of it is left.
+fn branch(stmt: Stmt) -> Stmt {
+    let span = stmt.span();
+    simplify_stmt(stmt).unwrap_or(Stmt::Block {
+        statements: Vec::new(),
+        span,
+    })
+}
+
+/// Bodies are shared with functions already created from them, which are
+/// left as they are.
+fn function_body(body: Rc<Vec<Stmt>>) -> Rc<Vec<Stmt>> {
+    match Rc::try_unwrap(body) {
+        Ok(body) => Rc::new(optimize(body)),
+        Err(body) => body,
+    }
+}
+
+fn simplify_expr(expr: Expr) -> Expr {
+    match expr {
+        Expr::Grouping {
+            expression, span, ..
+        } => {
+            // The unwrapped expression keeps the parentheses in its span, so
+            // errors still point at the same source.
+            let mut expression = simplify_expr(*expression);
+            *expression.span_mut() = span;
+            expression
+        }
+        Expr::Binary {
+            left,
+            operator,
+            right,
+            span,
+        } => {
+            let (left, right) = (simplify_expr(*left), simplify_expr(*right));
+            match fold_binary(&left, &operator, &right) {
+                Some(value) => literal(value, span),
+                None => Expr::Binary {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                    span,
+                },
+            }
+        }
+        Expr::Unary {
+            operator,
+            right,
+            span,
+        } => {
+            let right = simplify_expr(*right);
+            let folded =
+                literal_value(&right).and_then(|value| match (&operator.token_type, value) {
+                    (TokenType::Minus, Value::Number(n)) => Some(Value::Number(-n)),
+                    (TokenType::Bang, value) => Some(Value::Bool(!value.is_truthy())),
+                    _ => None,
+                });
+            match folded {
+                Some(value) => literal(value, span),
+                None => Expr::Unary {
+                    operator,
+                    right: Box::new(right),
+                    span,
+                },
+            }
+        }
+        Expr::Logical {
+            left,
+            operator,
+            right,
+            span,
+        } => {
+            let left = simplify_expr(*left);
+            let right = simplify_expr(*right);
+            match literal_value(&left) {
+                // The left operand decides the result without the right one.
+                Some(value) if value.is_truthy() == (operator.token_type == TokenType::Or) => {
+                    literal(value, span)
+                }
+                Some(_) => right,
+                None => Expr::Logical {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                    span,
+                },
+            }
+        }
+        Expr::Call {
+            callee,
+            paren,
+            arguments,
+            span,
+        } => Expr::Call {
+            callee: Box::new(simplify_expr(*callee)),
+            paren,
+            arguments: arguments.into_iter().map(simplify_expr).collect(),
+            span,
+        },
+        Expr::Assign {
+            name,
+            value,
+            binding,
+            span,
+        } => Expr::Assign {
+            name,
+            value: Box::new(simplify_expr(*value)),
+            binding,
+            span,
+        },
+        Expr::Get { object, name, span } => Expr::Get {
+            object: Box::new(simplify_expr(*object)),
+            name,
+            span,
+        },
+        Expr::Set {
+            object,
+            name,
+            value,
+            span,
+        } => Expr::Set {
+            object: Box::new(simplify_expr(*object)),
+            name,
+            value: Box::new(simplify_expr(*value)),
+            span,
+        },
+        expr @ (Expr::Literal { .. }
+        | Expr::Var { .. }
+        | Expr::Super { .. }
+        | Expr::This { .. }) => expr,
+    }
+}
+
+fn literal_value(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::Literal { value, .. } => Value::from_literal(&value.token_type),
+        _ => None,
+    }
+}
+
+fn literal(value: Value, span: Span) -> Expr {
+    let token_type = match value {
+        Value::Nil => TokenType::Nil,
+        Value::Bool(true) => TokenType::True,
+        Value::Bool(false) => TokenType::False,
+        Value::Number(n) => TokenType::Number(n),
+        Value::String(s) => TokenType::String(s.to_string()),
+        _ => unreachable!("only literal values are folded"),
+    };
+    Expr::Literal {
+        value: Token::new(token_type, span),
+        span,
+    }
+}
+
+/// The value of a binary expression over two literals, unless evaluating it
+/// would be a runtime error.
+fn fold_binary(left: &Expr, operator: &Token, right: &Expr) -> Option<Value> {
+    let (left, right) = (literal_value(left)?, literal_value(right)?);
+    let value = match (&operator.token_type, left, right) {
+        (TokenType::EqualEqual, l, r) => Value::Bool(l == r),
+        (TokenType::BangEqual, l, r) => Value::Bool(l != r),
+        (TokenType::Plus, Value::String(l), Value::String(r)) => {
+            Value::String(format!("{}{}", l, r).into())
+        }
+        (operator, Value::Number(l), Value::Number(r)) => match operator {
+            TokenType::Plus => Value::Number(l + r),
+            TokenType::Minus => Value::Number(l - r),
+            TokenType::Star => Value::Number(l * r),
+            TokenType::Slash => Value::Number(l / r),
+            TokenType::Greater => Value::Bool(l > r),
+            TokenType::GreaterEqual => Value::Bool(l >= r),
+            TokenType::Less => Value::Bool(l < r),
+            TokenType::LessEqual => Value::Bool(l <= r),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(value)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::parse_source, scanner::Dialect};
+    fn optimized(source: &str) -> Vec<Stmt> {
+        let (stmts, _) = parse_source(source, Dialect::Lox);
+        optimize(stmts)
+    }
+    fn printed(stmt: &Stmt) -> Option<Value> {
+        match stmt {
+            Stmt::Print { expression, .. } => literal_value(expression),
+            _ => None,
+        }
+    }
+    #[test]
+    fn folds_constant_expressions() {
+        let stmts = optimized(
+            "print 1 + 2 * 3;\nprint (\"a\" + \"b\") + \"c\";\nprint -(2 - 5) >= 3;\nprint !nil == true;\nprint nil or 4;",
+        );
+        let values: Vec<_> = stmts.iter().map(printed).collect();
+        assert_eq!(
+            values,
+            vec![
+                Some(Value::from(7.0)),
+                Some(Value::from("abc")),
+                Some(Value::from(true)),
+                Some(Value::from(true)),
+                Some(Value::from(4.0)),
+            ]
+        );
+        assert_eq!(stmts[0].span(), Span::new(0, 16, 1, 1));
+    }
+    #[test]
+    fn leaves_runtime_errors_in_place() {
+        let stmts = optimized("print \"a\" - 1;\nprint -\"b\";\nprint (nil) * 2;");
+        assert!(stmts.iter().all(|stmt| printed(stmt).is_none()));
+        // Unwrapping the grouping keeps its span for the error to point at.
+        match &stmts[2] {
+            Stmt::Print {
+                expression: Expr::Binary { left, .. },
+                ..
+            } => assert_eq!(left.span().end - left.span().start, 5),
+            other => panic!("{:?}", other),
+        }
+    }
+    #[test]
+    fn removes_dead_branches() {
+        let stmts = optimized(
+            "if (1 > 2) print 1; else print 2;\nif (false) print 3;\nwhile (!true) print 4;\nwhile (x) if (nil) print 5;",
+        );
+        assert_eq!(stmts.len(), 2);
+        assert_eq!(printed(&stmts[0]), Some(Value::from(2.0)));
+        match &stmts[1] {
+            Stmt::While { body, .. } => {
+                assert!(
+                    matches!(**body, Stmt::Block { ref statements, .. } if statements.is_empty())
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -1,47 +1,162 @@
-use std::mem;
+use std::{cell::Cell, mem, rc::Rc};
 
 use thiserror::Error;
 
 use crate::{
     ast::{Expr, Stmt},
-    tokens::{Token, TokenType},
+    tokens::{Span, Token, TokenType},
 };
 #[derive(Error, Debug)]
 pub enum ParseError {
-    #[error("Expected expression at line {0}")]
-    ExpectedExpression(usize),
-    #[error("{0}")]
-    Panic(String),
+    #[error("Expected expression at {0}")]
+    ExpectedExpression(Span),
+    #[error("{0} ({1})")]
+    Panic(String, Span),
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<ParseError>,
 }
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+        }
     }
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParseError> {
+    /// Parses the whole token stream. Syntax errors don't stop the parser: it
+    /// skips to the next statement and carries on, so this returns every
+    /// statement that parsed cleanly alongside every error found.
+    pub fn parse(&mut self) -> (Vec<Stmt>, Vec<ParseError>) {
         let mut stmt = Vec::new();
         while !self.is_at_end() {
-            stmt.push(self.declaration()?);
+            let before = self.current;
+            stmt.extend(self.recovering_declaration());
+            // A stray `}` is reported but not consumed by `synchronize`.
+            if self.current == before {
+                self.advance();
+            }
+        }
+        (stmt, mem::take(&mut self.errors))
+    }
+    /// Parses a declaration, recording any error and skipping to the start of
+    /// the next statement instead of failing.
+    fn recovering_declaration(&mut self) -> Option<Stmt> {
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(e) => {
+                // The scanner has already reported the character behind an
+                // error token, so only recover from it.
+                if !self.check(&TokenType::Error(' ')) {
+                    self.errors.push(e);
+                }
+                self.synchronize();
+                None
+            }
         }
-        Ok(stmt)
     }
     fn declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        if self.match_token(&[TokenType::Fun]) {
+            let start = self.previous().span;
+            return self.function("function", start);
+        }
         if self.match_token(&[TokenType::Var]) {
-            match self.var_declaration() {
-                Ok(stmt) => return Ok(stmt),
-                Err(e) => {
-                    self.synchronize();
-                    return Err(e);
+            return self.var_declaration();
+        }
+        self.statement()
+    }
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
+        let name = self
+            .consume(TokenType::Identifier("".to_string()), "Expect class name.")?
+            .clone();
+        let superclass = if self.match_token(&[TokenType::Less]) {
+            self.consume(
+                TokenType::Identifier("".to_string()),
+                "Expect superclass name.",
+            )?;
+            Some(Expr::Var {
+                name: self.previous().clone(),
+                binding: Cell::new(None),
+                span: self.previous().span,
+            })
+        } else {
+            None
+        };
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let start = self.peek().span;
+            methods.push(self.function("method", start)?);
+        }
+        let end = self
+            .consume(TokenType::RightBrace, "Expect '}' after class body.")?
+            .span;
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            methods,
+            span: start.to(end),
+        })
+    }
+    /// Parses a function or method after its introducer; `start` is where the
+    /// whole declaration begins.
+    fn function(&mut self, kind: &str, start: Span) -> Result<Stmt, ParseError> {
+        let name = self
+            .consume(
+                TokenType::Identifier("".to_string()),
+                &format!("Expect {} name.", kind),
+            )?
+            .clone();
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if params.len() >= 255 {
+                    return Err(ParseError::Panic(
+                        "Can't have more than 255 parameters.".to_string(),
+                        self.peek().span,
+                    ));
+                }
+                params.push(
+                    self.consume(
+                        TokenType::Identifier("".to_string()),
+                        "Expect parameter name.",
+                    )?
+                    .clone(),
+                );
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
                 }
             }
         }
-        self.statement()
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        let (body, end) = match self.block_statement()? {
+            Stmt::Block { statements, span } => (statements, span),
+            _ => unreachable!(),
+        };
+        Ok(Stmt::Function {
+            name,
+            params,
+            body: Rc::new(body),
+            span: start.to(end),
+        })
     }
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
         let name = self
             .consume(
                 TokenType::Identifier("".to_string()),
@@ -52,14 +167,21 @@ impl Parser {
             self.expression()?
         } else {
             Expr::Literal {
-                value: Token::new(TokenType::Nil, 0),
+                value: Token::new(TokenType::Nil, name.span),
+                span: name.span,
             }
         };
-        self.consume(
-            TokenType::Semicolon,
-            "Expect ';' after variable declaration.",
-        )?;
-        Ok(Stmt::Var { name, initializer })
+        let end = self
+            .consume(
+                TokenType::Semicolon,
+                "Expect ';' after variable declaration.",
+            )?
+            .span;
+        Ok(Stmt::Var {
+            name,
+            initializer,
+            span: start.to(end),
+        })
     }
     fn statement(&mut self) -> Result<Stmt, ParseError> {
         if self.match_token(&[TokenType::For]) {
@@ -71,6 +193,9 @@ impl Parser {
         if self.match_token(&[TokenType::Print]) {
             return self.print_statement();
         }
+        if self.match_token(&[TokenType::Return]) {
+            return self.return_statement();
+        }
         if self.match_token(&[TokenType::While]) {
             return self.while_statement();
         }
@@ -80,6 +205,7 @@ impl Parser {
         self.expression_statement()
     }
     fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().span;
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
         let initializer = if self.match_token(&[TokenType::Semicolon]) {
             None
@@ -93,7 +219,9 @@ impl Parser {
         } else {
             None
         };
-        self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+        let semicolon = self
+            .consume(TokenType::Semicolon, "Expect ';' after loop condition.")?
+            .span;
         let increment = if !self.check(&TokenType::RightParen) {
             Some(self.expression())
         } else {
@@ -101,45 +229,54 @@ impl Parser {
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
         let mut body = self.statement()?;
+        // Every statement synthesized by the desugaring covers the whole loop.
+        let span = keyword.to(body.span());
         if let Some(increment) = increment {
+            let increment = increment?;
             body = Stmt::Block {
                 statements: vec![
                     body,
                     Stmt::Expression {
-                        expression: increment?,
+                        span: increment.span(),
+                        expression: increment,
                     },
                 ],
+                span,
             };
         }
-        if let None = condition {
-            body = Stmt::While {
-                condition: Expr::Literal {
-                    value: Token::new(TokenType::True, 0),
+        body = Stmt::While {
+            condition: match condition {
+                Some(condition) => condition?,
+                None => Expr::Literal {
+                    value: Token::new(TokenType::True, semicolon),
+                    span: semicolon,
                 },
-                body: Box::new(body),
-            };
-        }
-        if let Some(condition) = condition {
-            body = Stmt::While {
-                condition: condition?,
-                body: Box::new(body),
-            };
-        }
+            },
+            body: Box::new(body),
+            span,
+        };
         if let Some(initializer) = initializer {
             body = Stmt::Block {
                 statements: vec![initializer?, body],
+                span,
             };
         }
-        return Ok(body);
+        Ok(body)
     }
     fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
         let body = Box::new(self.statement()?);
-        Ok(Stmt::While { condition, body })
+        Ok(Stmt::While {
+            condition,
+            span: start.to(body.span()),
+            body,
+        })
     }
     fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
         let condition = self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after if condition.")?;
@@ -149,29 +286,67 @@ impl Parser {
         } else {
             None
         };
+        let end = match &else_branch {
+            Some(else_branch) => else_branch.span(),
+            None => then_branch.span(),
+        };
         Ok(Stmt::If {
             condition,
             then_branch,
             else_branch,
+            span: start.to(end),
         })
     }
     fn block_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
         let mut statements = Vec::new();
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
-        }
-        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
-        Ok(Stmt::Block { statements })
+            statements.extend(self.recovering_declaration());
+        }
+        let end = self
+            .consume(TokenType::RightBrace, "Expect '}' after block.")?
+            .span;
+        Ok(Stmt::Block {
+            statements,
+            span: start.to(end),
+        })
     }
     fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous().span;
         let value = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print { expression: value })
+        let end = self
+            .consume(TokenType::Semicolon, "Expect ';' after value.")?
+            .span;
+        Ok(Stmt::Print {
+            expression: value,
+            span: start.to(end),
+        })
+    }
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let value = if !self.check(&TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        let end = self
+            .consume(TokenType::Semicolon, "Expect ';' after return value.")?
+            .span;
+        Ok(Stmt::Return {
+            span: keyword.span.to(end),
+            keyword,
+            value,
+        })
     }
     fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let value = self.expression()?;
-        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Expression { expression: value })
+        let end = self
+            .consume(TokenType::Semicolon, "Expect ';' after value.")?
+            .span;
+        Ok(Stmt::Expression {
+            span: value.span().to(end),
+            expression: value,
+        })
     }
     fn expression(&mut self) -> Result<Expr, ParseError> {
         self.assignment()
@@ -180,14 +355,30 @@ impl Parser {
         let expr = self.or()?;
         if self.match_token(&[TokenType::Equal]) {
             let value = self.assignment()?;
+            let span = expr.span().to(value.span());
             match expr {
-                Expr::Var { name } => {
+                Expr::Var { name, .. } => {
                     return Ok(Expr::Assign {
                         name,
                         value: Box::new(value),
+                        binding: Cell::new(None),
+                        span,
+                    })
+                }
+                Expr::Get { object, name, .. } => {
+                    return Ok(Expr::Set {
+                        object,
+                        name,
+                        value: Box::new(value),
+                        span,
                     })
                 }
-                _ => return Err(ParseError::Panic("Invalid assignment target.".to_string())),
+                _ => {
+                    return Err(ParseError::Panic(
+                        "Invalid assignment target.".to_string(),
+                        expr.span(),
+                    ))
+                }
             }
         }
         Ok(expr)
@@ -198,6 +389,7 @@ impl Parser {
             let operator = self.previous().clone();
             let right = self.and()?;
             expr = Expr::Logical {
+                span: expr.span().to(right.span()),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
@@ -211,6 +403,7 @@ impl Parser {
             let operator = self.previous().clone();
             let right = self.equality()?;
             expr = Expr::Logical {
+                span: expr.span().to(right.span()),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
@@ -224,6 +417,7 @@ impl Parser {
             let operator = self.previous().clone();
             let right = self.comparison()?;
             expr = Expr::Binary {
+                span: expr.span().to(right.span()),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
@@ -242,6 +436,7 @@ impl Parser {
             let operator = self.previous().clone();
             let right = self.term()?;
             expr = Expr::Binary {
+                span: expr.span().to(right.span()),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
@@ -255,6 +450,7 @@ impl Parser {
             let operator = self.previous().clone();
             let right = self.factor()?;
             expr = Expr::Binary {
+                span: expr.span().to(right.span()),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
@@ -268,6 +464,7 @@ impl Parser {
             let operator = self.previous().clone();
             let right = self.unary()?;
             expr = Expr::Binary {
+                span: expr.span().to(right.span()),
                 left: Box::new(expr),
                 operator,
                 right: Box::new(right),
@@ -280,66 +477,138 @@ impl Parser {
             let operator = self.previous().clone();
             let right = self.unary()?;
             return Ok(Expr::Unary {
+                span: operator.span.to(right.span()),
                 operator,
                 right: Box::new(right),
             });
         }
-        self.primary()
+        self.call()
     }
-    fn primary(&mut self) -> Result<Expr, ParseError> {
-        if self.match_token(&[TokenType::False]) {
-            return Ok(Expr::Literal {
-                value: self.previous().clone(),
-            });
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.match_token(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_token(&[TokenType::Dot]) {
+                let name = self
+                    .consume(
+                        TokenType::Identifier("".to_string()),
+                        "Expect property name after '.'.",
+                    )?
+                    .clone();
+                expr = Expr::Get {
+                    span: expr.span().to(name.span),
+                    object: Box::new(expr),
+                    name,
+                };
+            } else {
+                break;
+            }
         }
-        if self.match_token(&[TokenType::True]) {
-            return Ok(Expr::Literal {
-                value: self.previous().clone(),
-            });
+        Ok(expr)
+    }
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    return Err(ParseError::Panic(
+                        "Can't have more than 255 arguments.".to_string(),
+                        self.peek().span,
+                    ));
+                }
+                arguments.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
-        if self.match_token(&[TokenType::Nil]) {
+        let paren = self
+            .consume(TokenType::RightParen, "Expect ')' after arguments.")?
+            .clone();
+        Ok(Expr::Call {
+            span: callee.span().to(paren.span),
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        })
+    }
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        if self.match_token(&[
+            TokenType::False,
+            TokenType::True,
+            TokenType::Nil,
+            TokenType::Number(0.0),
+            TokenType::String(String::new()),
+        ]) {
             return Ok(Expr::Literal {
                 value: self.previous().clone(),
+                span: self.previous().span,
             });
         }
-        if self.match_token(&[TokenType::Number(0.0)]) {
-            return Ok(Expr::Literal {
-                value: self.previous().clone(),
+        if self.match_token(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self
+                .consume(
+                    TokenType::Identifier("".to_string()),
+                    "Expect superclass method name.",
+                )?
+                .clone();
+            return Ok(Expr::Super {
+                span: keyword.span.to(method.span),
+                keyword,
+                method,
+                binding: Cell::new(None),
             });
         }
-        if self.match_token(&[TokenType::String(String::new())]) {
-            return Ok(Expr::Literal {
-                value: self.previous().clone(),
+        if self.match_token(&[TokenType::This]) {
+            return Ok(Expr::This {
+                keyword: self.previous().clone(),
+                binding: Cell::new(None),
+                span: self.previous().span,
             });
         }
         if self.match_token(&[TokenType::Identifier(String::new())]) {
             return Ok(Expr::Var {
                 name: self.previous().clone(),
+                binding: Cell::new(None),
+                span: self.previous().span,
             });
         }
         if self.match_token(&[TokenType::LeftParen]) {
+            let start = self.previous().span;
             let expr = self.expression()?;
-            self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
+            let end = self
+                .consume(TokenType::RightParen, "Expect ')' after expression.")?
+                .span;
             return Ok(Expr::Grouping {
                 expression: Box::new(expr),
+                span: start.to(end),
             });
         }
-        Err(ParseError::ExpectedExpression(self.peek().line))
+        Err(ParseError::ExpectedExpression(self.peek().span))
     }
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<&Token, ParseError> {
         if self.check(&token_type) {
             Ok(self.advance())
         } else {
-            Err(ParseError::Panic(message.to_string()))
+            Err(ParseError::Panic(message.to_string(), self.peek().span))
         }
     }
     fn synchronize(&mut self) {
-        self.advance();
+        // The closing brace of the enclosing block is left for it to consume,
+        // but braces opened while skipping are skipped along with their body.
+        let mut depth = 0usize;
         while !self.is_at_end() {
-            if self.previous().token_type == TokenType::Semicolon {
-                return;
-            }
             match self.peek().token_type {
+                TokenType::RightBrace if depth == 0 => return,
+                TokenType::RightBrace => depth -= 1,
+                TokenType::LeftBrace => depth += 1,
+                TokenType::Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
                 TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
@@ -347,7 +616,11 @@ impl Parser {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                    if depth == 0 =>
+                {
+                    return
+                }
                 _ => {}
             }
             self.advance();
@@ -384,3 +657,65 @@ impl Parser {
         &self.tokens[self.current - 1]
     }
 }
+/// Scans and parses `source` for a test, failing it on any scan or parse
+/// error. The comments are returned alongside the statements.
+#[cfg(test)]
+pub(crate) fn parse_source(
+    source: &str,
+    dialect: crate::scanner::Dialect,
+) -> (Vec<Stmt>, Vec<crate::tokens::Comment>) {
+    let mut scanner = crate::scanner::Scanner::with_dialect(source, dialect);
+    let (tokens, errors) = scanner.scan_tokens();
+    assert!(errors.is_empty(), "{:?}", errors);
+    let (stmts, errors) = Parser::new(tokens).parse();
+    assert!(errors.is_empty(), "{:?}", errors);
+    (stmts, scanner.comments)
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scanner::Scanner;
+    fn parse(source: &str) -> (Vec<Stmt>, Vec<ParseError>) {
+        let (tokens, errors) = Scanner::new(source).scan_tokens();
+        assert!(errors.is_empty());
+        Parser::new(tokens).parse()
+    }
+    fn error_lines(errors: &[ParseError]) -> Vec<usize> {
+        errors
+            .iter()
+            .map(|e| match e {
+                ParseError::ExpectedExpression(span) | ParseError::Panic(_, span) => span.line,
+            })
+            .collect()
+    }
+    #[test]
+    fn reports_every_error() {
+        let (stmts, errors) = parse("print 1 +;\nvar = 2;\nprint 3;\nfun f( {}\nprint 4;\n");
+        assert_eq!(error_lines(&errors), vec![1, 2, 4]);
+        assert_eq!(stmts.len(), 2);
+    }
+    #[test]
+    fn recovers_inside_blocks() {
+        let (stmts, errors) = parse("{\n  print ;\n  print 1;\n  1 +\n}\nprint 2;\n");
+        assert_eq!(error_lines(&errors), vec![2, 5]);
+        assert_eq!(stmts.len(), 2);
+        match &stmts[0] {
+            Stmt::Block { statements, .. } => assert_eq!(statements.len(), 1),
+            other => panic!("{:?}", other),
+        }
+    }
+    #[test]
+    fn stray_closing_brace() {
+        let (stmts, errors) = parse("}\nprint 1;\n");
+        assert_eq!(error_lines(&errors), vec![1]);
+        assert_eq!(stmts.len(), 1);
+    }
+    #[test]
+    fn error_tokens_are_not_reported_again() {
+        let (tokens, scan_errors) = Scanner::new("print 1 @ 2;\nprint 3;\n").scan_tokens();
+        assert_eq!(scan_errors.len(), 1);
+        let (stmts, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(stmts.len(), 1);
+    }
+}
@@ -0,0 +1,573 @@
+use log::warn;
+use rustyline::{
+    completion::Completer,
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::{ValidationContext, ValidationResult, Validator},
+    Context, Editor, Helper,
+};
+
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ast::{Expr, Stmt},
+    diagnostics::Diagnostic,
+    dump::{self, DumpFormat},
+    parser::Parser,
+    read_source, report,
+    scanner::{Dialect, ScanError, Scanner},
+    syntax_errors,
+    tokens::{Span, Token, TokenType, KEYWORDS},
+    LoxError, Options, Runtime, Sources,
+};
+
+const PROMPT: &str = ">> ";
+/// Shown while the input so far is an unfinished statement.
+const CONTINUATION_PROMPT: &str = ".. ";
+
+/// Whether `source` is the start of a program rather than a broken one: it
+/// leaves a string, comment or bracket open, or only fails to parse because
+/// it ends too early, like `print 1` without its `;`.
+pub fn is_incomplete(source: &str, dialect: Dialect) -> bool {
+    let (tokens, scan_errors) = Scanner::with_dialect(source, dialect).scan_tokens();
+    if scan_errors.iter().any(|e| {
+        matches!(
+            e,
+            ScanError::UnterminatedString(_)
+                | ScanError::UnterminatedComment(_)
+                | ScanError::UnexpectedEndOfFile(_)
+        )
+    }) {
+        return true;
+    }
+    let depth = tokens
+        .iter()
+        .fold(0, |depth, token| match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace => depth + 1,
+            TokenType::RightParen | TokenType::RightBrace => depth - 1,
+            _ => depth,
+        });
+    if depth > 0 {
+        return true;
+    }
+    let (_, parse_errors) = Parser::new(tokens).parse();
+    let end = source.trim_end().len();
+    !parse_errors.is_empty()
+        && parse_errors.iter().all(|e| {
+            Diagnostic::from(e)
+                .span
+                .is_some_and(|span| span.start >= end)
+        })
+}
+
+/// Rewrites top-level expression statements into prints, so that entering
+/// an expression shows its value. Assignments are left alone, and calls only
+/// show a value other than `nil`, since most are made for their effect.
+pub(crate) fn echo(statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements
+        .into_iter()
+        .map(|stmt| match stmt {
+            Stmt::Expression {
+                expression: expression @ Expr::Call { .. },
+                span,
+            } => echo_unless_nil(expression, span),
+            Stmt::Expression { expression, span }
+                if !matches!(expression, Expr::Assign { .. } | Expr::Set { .. }) =>
+            {
+                Stmt::Print { expression, span }
+            }
+            stmt => stmt,
+        })
+        .collect()
+}
+/// `{ var result = call; if (result != nil) print result; }`, with a name no
+/// script can write.
+fn echo_unless_nil(call: Expr, span: Span) -> Stmt {
+    let name = Token::new(TokenType::Identifier(" result".to_string()), span);
+    let result = || Expr::Var {
+        name: name.clone(),
+        binding: Cell::new(None),
+        span,
+    };
+    let condition = Expr::Binary {
+        left: Box::new(result()),
+        operator: Token::new(TokenType::BangEqual, span),
+        right: Box::new(Expr::Literal {
+            value: Token::new(TokenType::Nil, span),
+            span,
+        }),
+        span,
+    };
+    Stmt::Block {
+        statements: vec![
+            Stmt::Var {
+                name: name.clone(),
+                initializer: call,
+                span,
+            },
+            Stmt::If {
+                condition,
+                then_branch: Box::new(Stmt::Print {
+                    expression: result(),
+                    span,
+                }),
+                else_branch: None,
+                span,
+            },
+        ],
+        span,
+    }
+}
+
+const COMMANDS: &[&str] = &[
+    ":help", ":tokens", ":ast", ":env", ":load", ":reset", ":quit",
+];
+const HELP: &str = "\
+Enter statements to run them, or an expression to print its value.
+Commands:
+  :help           Show this message
+  :tokens <code>  Print the tokens of <code>
+  :ast <code>     Print the syntax tree of <code>
+  :env            Print the global variables
+  :load <file>    Run a file in this session
+  :reset          Forget every variable
+  :quit           Leave the REPL
+";
+
+/// Whether the session goes on after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// An interactive session: the runtime keeps its globals from one input to
+/// the next.
+struct Repl {
+    runtime: Runtime,
+    /// Every input so far, for errors raised by code from an earlier one.
+    sources: Sources,
+    options: Options,
+}
+impl Repl {
+    fn new(options: Options) -> Self {
+        Self {
+            runtime: Runtime::new(options.backend),
+            sources: Sources::default(),
+            options,
+        }
+    }
+    fn names(&self) -> Vec<String> {
+        self.runtime
+            .globals()
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+    fn run(&mut self, source: &str) {
+        // Errors have been reported, and the runtime is still usable.
+        let _ = crate::run(
+            &mut self.sources,
+            source,
+            "<repl>",
+            &mut self.runtime,
+            self.options,
+            true,
+        );
+    }
+    /// Runs the script at `path` in this session. Errors are reported, and
+    /// only failing to read it is returned.
+    fn load(&mut self, path: &Path) -> Result<(), LoxError> {
+        let source = read_source(path, self.options)?;
+        let file_name = path.display().to_string();
+        let _ = crate::run(
+            &mut self.sources,
+            &source,
+            &file_name,
+            &mut self.runtime,
+            self.options,
+            false,
+        );
+        Ok(())
+    }
+    /// Runs a `:` command. Errors are reported and do not end the session.
+    fn command(&mut self, line: &str) -> Flow {
+        let (name, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+        let repl = Path::new("<repl>");
+        match name {
+            ":help" => print!("{}", HELP),
+            ":tokens" => {
+                let (tokens, errors) =
+                    Scanner::with_dialect(argument, self.options.dialect).scan_tokens();
+                print!("{}", dump::tokens(&tokens, DumpFormat::Tree));
+                if let Some(e) = syntax_errors(errors, Vec::new()) {
+                    report(repl, argument, self.options, e);
+                }
+            }
+            ":ast" => {
+                let (tokens, scan_errors) =
+                    Scanner::with_dialect(argument, self.options.dialect).scan_tokens();
+                let (stmt, parse_errors) = Parser::new(tokens).parse();
+                match syntax_errors(scan_errors, parse_errors) {
+                    Some(e) => {
+                        report(repl, argument, self.options, e);
+                    }
+                    None => print!("{}", dump::ast(&stmt, DumpFormat::Tree)),
+                }
+            }
+            ":env" => {
+                for (name, value) in self.runtime.globals().iter() {
+                    println!("{} = {}", name, value);
+                }
+            }
+            ":load" => {
+                // Errors are reported, and the session goes on.
+                let _ = self.load(Path::new(argument));
+            }
+            ":reset" => *self = Repl::new(self.options),
+            ":quit" => return Flow::Quit,
+            _ => eprintln!("Unknown command '{}'. Type :help for a list.", name),
+        }
+        Flow::Continue
+    }
+}
+
+const KEYWORD: &str = "\x1b[1;35m";
+const LITERAL: &str = "\x1b[33m";
+const STRING: &str = "\x1b[32m";
+const COMMENT: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Editing support for the prompt: completion of keywords, commands and
+/// globals, highlighting with the scanner, and a check that brackets match
+/// before a line is accepted.
+struct LoxHelper {
+    dialect: Dialect,
+    color: bool,
+    /// Whether input comes from a terminal, where it can be edited.
+    interactive: bool,
+    /// The globals defined in the session, updated after each input.
+    names: Vec<String>,
+}
+impl LoxHelper {
+    fn new(options: Options) -> Self {
+        Self {
+            dialect: options.dialect,
+            color: options.color.enabled(std::io::stdout()),
+            interactive: std::io::stdin().is_terminal(),
+            names: Vec::new(),
+        }
+    }
+    /// Rejects a closing bracket that does not match the last one opened,
+    /// and keeps reading while any are left open.
+    fn check_brackets(&self, input: &str) -> ValidationResult {
+        let (tokens, _) = Scanner::with_dialect(input, self.dialect).scan_tokens();
+        let mut open = Vec::new();
+        for token in &tokens {
+            let expected = match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBrace => {
+                    open.push(&token.token_type);
+                    continue;
+                }
+                TokenType::RightParen => TokenType::LeftParen,
+                TokenType::RightBrace => TokenType::LeftBrace,
+                _ => continue,
+            };
+            if open.pop() != Some(&expected) {
+                return ValidationResult::Invalid(Some(format!(
+                    "  <- unmatched '{}'",
+                    token.token_type
+                )));
+            }
+        }
+        if open.is_empty() {
+            ValidationResult::Valid(None)
+        } else {
+            ValidationResult::Incomplete
+        }
+    }
+    fn style(token_type: &TokenType) -> Option<&'static str> {
+        match token_type {
+            TokenType::String(_) => Some(STRING),
+            TokenType::Number(_) | TokenType::True | TokenType::False | TokenType::Nil => {
+                Some(LITERAL)
+            }
+            token_type if KEYWORDS.values().any(|keyword| keyword == token_type) => Some(KEYWORD),
+            _ => None,
+        }
+    }
+}
+impl Completer for LoxHelper {
+    type Candidate = String;
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        if start == 1 && line.starts_with(':') {
+            let commands = COMMANDS
+                .iter()
+                .filter(|command| command[1..].starts_with(prefix))
+                .map(|command| command[1..].to_string())
+                .collect();
+            return Ok((start, commands));
+        }
+        let mut candidates: Vec<_> = KEYWORDS
+            .keys()
+            .copied()
+            .chain(self.names.iter().map(String::as_str))
+            .filter(|name| name.starts_with(prefix))
+            .map(str::to_string)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+impl Highlighter for LoxHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        if !self.color || line.starts_with(':') {
+            return Cow::Borrowed(line);
+        }
+        let mut scanner = Scanner::with_dialect(line, self.dialect);
+        let (tokens, _) = scanner.scan_tokens();
+        let mut spans: Vec<_> = tokens
+            .iter()
+            .filter_map(|token| Some((token.span, Self::style(&token.token_type)?)))
+            .chain(
+                scanner
+                    .comments
+                    .iter()
+                    .map(|comment| (comment.span, COMMENT)),
+            )
+            .collect();
+        spans.sort_by_key(|(span, _)| span.start);
+        let mut out = String::with_capacity(line.len());
+        let mut end = 0;
+        for (span, style) in spans {
+            out.push_str(&line[end..span.start]);
+            out.push_str(style);
+            out.push_str(&line[span.start..span.end]);
+            out.push_str(RESET);
+            end = span.end;
+        }
+        out.push_str(&line[end..]);
+        Cow::Owned(out)
+    }
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        self.color
+    }
+}
+impl Validator for LoxHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        // Piped input can't be corrected, so it is left to the parser.
+        if !self.interactive {
+            return Ok(ValidationResult::Valid(None));
+        }
+        Ok(self.check_brackets(ctx.input()))
+    }
+}
+impl Helper for LoxHelper {}
+
+/// Where the history is kept between sessions, in the user's data
+/// directory.
+fn history_file() -> Option<PathBuf> {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(std::env::var_os("APPDATA")?),
+        None => {
+            let home = PathBuf::from(std::env::var_os("HOME")?);
+            if cfg!(target_os = "macos") {
+                home.join("Library/Application Support")
+            } else {
+                home.join(".local/share")
+            }
+        }
+    };
+    Some(data_dir.join("lox_rs_ast").join("history.txt"))
+}
+
+/// Reads statements and commands from the terminal and runs them until end
+/// of input. Errors are reported and the session goes on with the globals
+/// defined so far. Interrupting discards a statement being entered, or ends
+/// the session if there is none.
+///
+/// With a `script`, the session starts by running it, so that its globals
+/// can be inspected.
+pub fn run_prompt(options: Options, script: Option<&Path>) -> Result<(), LoxError> {
+    let mut repl = Repl::new(options);
+    if let Some(script) = script {
+        repl.load(script)?;
+    }
+    let mut rl = Editor::<LoxHelper, DefaultHistory>::new()
+        .map_err(|e| report(Path::new(""), "", options, e.into()))?;
+    rl.set_helper(Some(LoxHelper::new(options)));
+    let history = history_file();
+    if let Some(path) = &history {
+        // There is no history before the first session.
+        let _ = rl.load_history(path);
+    }
+    let result =
+        read_eval_print(&mut rl, repl).map_err(|e| report(Path::new(""), "", options, e.into()));
+    if let Some(path) = &history {
+        let saved = match path.parent() {
+            Some(dir) => std::fs::create_dir_all(dir).map_err(ReadlineError::from),
+            None => Ok(()),
+        }
+        .and_then(|()| rl.save_history(path));
+        if let Err(e) = saved {
+            warn!("Could not save history to {}: {}", path.display(), e);
+        }
+    }
+    result
+}
+fn read_eval_print(
+    rl: &mut Editor<LoxHelper, DefaultHistory>,
+    mut repl: Repl,
+) -> Result<(), ReadlineError> {
+    let mut buffer = String::new();
+    loop {
+        if let Some(helper) = rl.helper_mut() {
+            helper.names = repl.names();
+        }
+        let prompt = if buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        match rl.readline(prompt) {
+            Ok(line) if buffer.is_empty() && line.trim_start().starts_with(':') => {
+                rl.add_history_entry(line.as_str())?;
+                if repl.command(line.trim()) == Flow::Quit {
+                    return Ok(());
+                }
+                continue;
+            }
+            Ok(line) => {
+                buffer.push_str(&line);
+                buffer.push('\n');
+            }
+            Err(ReadlineError::Interrupted) if !buffer.is_empty() => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) if !buffer.trim().is_empty() => {
+                // Input ended inside a statement. Running it reports what is
+                // missing instead of dropping it.
+                rl.add_history_entry(buffer.trim_end())?;
+                repl.run(&buffer);
+                return Ok(());
+            }
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if is_incomplete(&buffer, repl.options.dialect) {
+            continue;
+        }
+        let source = std::mem::take(&mut buffer);
+        if source.trim().is_empty() {
+            continue;
+        }
+        rl.add_history_entry(source.trim_end())?;
+        repl.run(&source);
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn unfinished_statements_are_incomplete() {
+        for source in [
+            "print 1",
+            "{",
+            "fun f() {\n  print 1;\n",
+            "if (x)",
+            "print \"abc",
+            "/* a",
+            "print (1 +",
+        ] {
+            assert!(is_incomplete(source, Dialect::Lox), "{:?}", source);
+        }
+    }
+    #[test]
+    fn expressions_are_echoed_but_assignments_are_not() {
+        let (tokens, _) = Scanner::new("a;\na = 1;\nprint a;").scan_tokens();
+        let (stmts, _) = Parser::new(tokens).parse();
+        let kinds: Vec<_> = echo(stmts)
+            .iter()
+            .map(|stmt| matches!(stmt, Stmt::Print { .. }))
+            .collect();
+        assert_eq!(kinds, [true, false, true]);
+    }
+    fn helper() -> LoxHelper {
+        LoxHelper {
+            dialect: Dialect::Lox,
+            color: true,
+            interactive: true,
+            names: vec!["counter".to_string(), "clock".to_string()],
+        }
+    }
+    #[test]
+    fn completes_keywords_globals_and_commands() {
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let complete = |line: &str| helper().complete(line, line.len(), &ctx).unwrap();
+        assert_eq!(
+            complete("print c"),
+            (
+                6,
+                vec!["class".to_string(), "clock".into(), "counter".into()]
+            )
+        );
+        assert_eq!(complete("wh"), (0, vec!["while".to_string()]));
+        assert_eq!(complete(":re"), (1, vec!["reset".to_string()]));
+    }
+    #[test]
+    fn highlights_with_the_scanner() {
+        assert_eq!(
+            helper().highlight("var s = \"a\"; // c", 0),
+            format!(
+                "{}var{} s = {}\"a\"{}; {}// c{}",
+                KEYWORD, RESET, STRING, RESET, COMMENT, RESET
+            )
+        );
+    }
+    #[test]
+    fn brackets_must_match() {
+        let helper = helper();
+        assert!(matches!(
+            helper.check_brackets("{ print (1); }"),
+            ValidationResult::Valid(None)
+        ));
+        assert!(matches!(
+            helper.check_brackets("fun f() { \"}\""),
+            ValidationResult::Incomplete
+        ));
+        assert!(matches!(
+            helper.check_brackets("print (1};"),
+            ValidationResult::Invalid(Some(_))
+        ));
+    }
+    #[test]
+    fn errors_and_complete_statements_are_not() {
+        for source in ["print 1;", "{}\n", "print 1 +;", "print );", "}", "@"] {
+            assert!(!is_incomplete(source, Dialect::Lox), "{:?}", source);
+        }
+    }
+}
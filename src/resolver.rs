@@ -0,0 +1,509 @@
+use std::{collections::HashMap, hint::unreachable_unchecked, mem};
+
+use thiserror::Error;
+
+use crate::{
+    ast::{Binding, Expr, ExprVisitor, Stmt, StmtVisitor},
+    environment::Globals,
+    tokens::{Span, Token},
+};
+#[derive(Error, Debug)]
+pub enum ResolveError {
+    #[error("Can't read local variable in its own initializer at {0}")]
+    ReadInOwnInitializer(Span),
+    #[error("Already a variable named '{1}' in this scope at {0}")]
+    AlreadyDeclared(Span, String),
+    #[error("Can't return from top-level code at {0}")]
+    TopLevelReturn(Span),
+    #[error("Can't return a value from an initializer at {0}")]
+    ReturnFromInitializer(Span),
+    #[error("Can't use 'this' outside of a class at {0}")]
+    ThisOutsideClass(Span),
+    #[error("Can't use 'super' outside of a class at {0}")]
+    SuperOutsideClass(Span),
+    #[error("Can't use 'super' in a class with no superclass at {0}")]
+    SuperWithoutSuperclass(Span),
+    #[error("A class can't inherit from itself at {0}")]
+    InheritsFromItself(Span),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+/// A local variable in one of the scopes being resolved.
+struct Local {
+    slot: usize,
+    defined: bool,
+}
+
+/// Walks the program before it runs, binding every variable use to the slot
+/// of the scope that declares it, or to its index in the globals table, and
+/// reporting errors that need no execution to find.
+///
+/// The scopes opened here must mirror the ones the interpreter creates: one
+/// per block, one for a function's parameters and body, one holding `super`
+/// for a subclass' methods and one holding `this` for every method.
+pub struct Resolver<'a> {
+    scopes: Vec<HashMap<String, Local>>,
+    globals: &'a mut Globals,
+    function: FunctionKind,
+    class: ClassKind,
+    errors: Vec<ResolveError>,
+}
+impl<'a> Resolver<'a> {
+    /// A resolver interning global names into `globals`, which must be the
+    /// table of the interpreter that will run the code.
+    pub fn new(globals: &'a mut Globals) -> Self {
+        Self {
+            scopes: Vec::new(),
+            globals,
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            errors: Vec::new(),
+        }
+    }
+    /// Resolves `statements`, recording bindings in the AST, and returns
+    /// every error found.
+    pub fn resolve(&mut self, statements: &[Stmt]) -> Vec<ResolveError> {
+        self.resolve_statements(statements);
+        mem::take(&mut self.errors)
+    }
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            stmt.accept(self);
+        }
+    }
+    fn resolve_expr(&mut self, expr: &Expr) {
+        expr.accept(self)
+    }
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            self.globals.intern(&name.lexeme());
+            return;
+        };
+        let lexeme = name.lexeme();
+        if scope.contains_key(&lexeme) {
+            self.errors
+                .push(ResolveError::AlreadyDeclared(name.span, lexeme));
+            return;
+        }
+        let slot = scope.len();
+        scope.insert(
+            lexeme,
+            Local {
+                slot,
+                defined: false,
+            },
+        );
+    }
+    fn define(&mut self, name: &Token) {
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme()))
+        {
+            local.defined = true;
+        }
+    }
+    /// Declares and defines a variable the interpreter adds implicitly, such
+    /// as `this`.
+    fn define_implicit(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        let slot = scope.len();
+        scope.insert(
+            name.to_string(),
+            Local {
+                slot,
+                defined: true,
+            },
+        );
+    }
+    fn resolve_local(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                scope.get(name).map(|local| Binding::Local {
+                    depth,
+                    slot: local.slot,
+                })
+            })
+    }
+    fn resolve_variable(&mut self, name: &str) -> Binding {
+        self.resolve_local(name)
+            .unwrap_or_else(|| Binding::Global(self.globals.intern(name)))
+    }
+    fn resolve_function(&mut self, params: &[Token], body: &[Stmt], kind: FunctionKind) {
+        let enclosing = mem::replace(&mut self.function, kind);
+        self.begin_scope();
+        for param in params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(body);
+        self.end_scope();
+        self.function = enclosing;
+    }
+}
+impl StmtVisitor<()> for Resolver<'_> {
+    fn visit_print_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print { expression, .. } => self.resolve_expr(expression),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_return_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Return { keyword, value, .. } => {
+                if self.function == FunctionKind::None {
+                    self.errors.push(ResolveError::TopLevelReturn(keyword.span));
+                }
+                if let Some(value) = value {
+                    if self.function == FunctionKind::Initializer {
+                        self.errors
+                            .push(ResolveError::ReturnFromInitializer(keyword.span));
+                    }
+                    self.resolve_expr(value);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_function_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Function {
+                name, params, body, ..
+            } => {
+                // Defined straight away so the function can call itself.
+                self.declare(name);
+                self.define(name);
+                self.resolve_function(params, body, FunctionKind::Function);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_if_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.resolve_expr(condition);
+                then_branch.accept(self);
+                if let Some(else_branch) = else_branch {
+                    else_branch.accept(self);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_expr_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression { expression, .. } => self.resolve_expr(expression),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                self.declare(name);
+                self.resolve_expr(initializer);
+                self.define(name);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_block_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements, .. } => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_while_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.resolve_expr(condition);
+                body.accept(self);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_class_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                let enclosing = mem::replace(&mut self.class, ClassKind::Class);
+                self.declare(name);
+                self.define(name);
+                if let Some(superclass) = superclass {
+                    if let Expr::Var {
+                        name: super_name, ..
+                    } = superclass
+                    {
+                        if super_name.lexeme() == name.lexeme() {
+                            self.errors
+                                .push(ResolveError::InheritsFromItself(super_name.span));
+                        }
+                    }
+                    self.class = ClassKind::Subclass;
+                    self.resolve_expr(superclass);
+                    self.begin_scope();
+                    self.define_implicit("super");
+                }
+                self.begin_scope();
+                self.define_implicit("this");
+                for method in methods {
+                    if let Stmt::Function {
+                        name, params, body, ..
+                    } = method
+                    {
+                        let kind = if name.lexeme() == "init" {
+                            FunctionKind::Initializer
+                        } else {
+                            FunctionKind::Method
+                        };
+                        self.resolve_function(params, body, kind);
+                    }
+                }
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.class = enclosing;
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
+impl ExprVisitor<()> for Resolver<'_> {
+    fn visit_binary_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_call_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.resolve_expr(callee);
+                for argument in arguments {
+                    self.resolve_expr(argument);
+                }
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_grouping_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Grouping { expression, .. } => self.resolve_expr(expression),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_literal_expr(&mut self, _expr: &Expr) {}
+    fn visit_unary_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Unary { right, .. } => self.resolve_expr(right),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_var_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Var { name, binding, .. } => {
+                let lexeme = name.lexeme();
+                if let Some(Local { defined: false, .. }) =
+                    self.scopes.last().and_then(|scope| scope.get(&lexeme))
+                {
+                    self.errors
+                        .push(ResolveError::ReadInOwnInitializer(name.span));
+                }
+                binding.set(Some(self.resolve_variable(&lexeme)));
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_assign_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign {
+                name,
+                value,
+                binding,
+                ..
+            } => {
+                self.resolve_expr(value);
+                binding.set(Some(self.resolve_variable(&name.lexeme())));
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_logical_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Logical { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_get_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Get { object, .. } => self.resolve_expr(object),
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_set_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Set { object, value, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_super_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Super {
+                keyword, binding, ..
+            } => match self.class {
+                ClassKind::None => self
+                    .errors
+                    .push(ResolveError::SuperOutsideClass(keyword.span)),
+                ClassKind::Class => self
+                    .errors
+                    .push(ResolveError::SuperWithoutSuperclass(keyword.span)),
+                ClassKind::Subclass => binding.set(self.resolve_local("super")),
+            },
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+    fn visit_this_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::This {
+                keyword, binding, ..
+            } => {
+                if self.class == ClassKind::None {
+                    self.errors
+                        .push(ResolveError::ThisOutsideClass(keyword.span));
+                    return;
+                }
+                binding.set(self.resolve_local("this"));
+            }
+            _ => unsafe { unreachable_unchecked() },
+        }
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::parse_source, scanner::Dialect};
+    fn resolve(source: &str) -> (Vec<Stmt>, Vec<ResolveError>) {
+        let (stmts, _) = parse_source(source, Dialect::Lox);
+        let errors = Resolver::new(&mut Globals::default()).resolve(&stmts);
+        (stmts, errors)
+    }
+    #[test]
+    fn binds_locals_by_depth_and_slot() {
+        let (stmts, errors) = resolve("var f; var g; { var a; var b; { print b; print g; } }");
+        assert!(errors.is_empty());
+        let Stmt::Block { statements, .. } = &stmts[2] else {
+            panic!()
+        };
+        let Stmt::Block { statements, .. } = &statements[2] else {
+            panic!()
+        };
+        let bindings: Vec<_> = statements
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Print {
+                    expression: Expr::Var { binding, .. },
+                    ..
+                } => binding.get(),
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            bindings,
+            vec![
+                Some(Binding::Local { depth: 1, slot: 1 }),
+                Some(Binding::Global(1))
+            ]
+        );
+    }
+    #[test]
+    fn reports_static_errors() {
+        let (_, errors) = resolve(
+            "return 1;\n\
+             { var a = a; }\n\
+             fun f(x, x) { var y; var y; }\n\
+             class A { init() { return 1; } m() { super.m(); } }\n\
+             class B < B {}\n\
+             print this;",
+        );
+        let kinds: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                ResolveError::TopLevelReturn(span) => ("return", span.line),
+                ResolveError::ReadInOwnInitializer(span) => ("initializer", span.line),
+                ResolveError::AlreadyDeclared(span, _) => ("duplicate", span.line),
+                ResolveError::ReturnFromInitializer(span) => ("init", span.line),
+                ResolveError::SuperWithoutSuperclass(span) => ("super", span.line),
+                ResolveError::InheritsFromItself(span) => ("inherit", span.line),
+                ResolveError::ThisOutsideClass(span) => ("this", span.line),
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("return", 1),
+                ("initializer", 2),
+                ("duplicate", 3),
+                ("duplicate", 3),
+                ("init", 4),
+                ("super", 4),
+                ("inherit", 5),
+                ("this", 6),
+            ]
+        );
+    }
+    #[test]
+    fn globals_may_be_redeclared() {
+        let (_, errors) = resolve("var a = 1; var a = a;");
+        assert!(errors.is_empty());
+    }
+}
@@ -1,69 +1,149 @@
-use crate::tokens::{Token, TokenType, KEYWORDS};
+use crate::tokens::{Comment, Span, Token, TokenType, KEYWORDS};
+use clap::ValueEnum;
 use log::debug;
 use thiserror::Error;
 
+/// Which flavour of the language the scanner accepts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Dialect {
+    /// The language as specified by Crafting Interpreters, plus string
+    /// escapes and raw strings, which every dialect has.
+    #[default]
+    Lox,
+    /// Lox plus exponents, `0x` hex literals, `_` digit separators and
+    /// Unicode identifiers.
+    Extended,
+}
+impl Dialect {
+    /// Identifiers are ASCII in plain Lox. The extended dialect accepts any
+    /// Unicode identifier, as defined by `XID_Start` and `XID_Continue`.
+    fn is_ident_start(self, c: char) -> bool {
+        match self {
+            Dialect::Lox => c.is_ascii_alphabetic() || c == '_',
+            Dialect::Extended => c == '_' || unicode_ident::is_xid_start(c),
+        }
+    }
+    fn is_ident_continue(self, c: char) -> bool {
+        match self {
+            Dialect::Lox => c.is_ascii_alphanumeric() || c == '_',
+            Dialect::Extended => unicode_ident::is_xid_continue(c),
+        }
+    }
+}
+
+/// Turns source text into tokens. The scanner walks the source one `char` at
+/// a time while keeping byte offsets for spans, so multi-byte UTF-8 text is
+/// never split.
 pub struct Scanner<'a> {
     pub source: &'a str,
     pub tokens: Vec<Token>,
+    pub errors: Vec<ScanError>,
+    /// Every comment, in source order. They are not tokens, so the parser
+    /// never sees them, but tools like the formatter need them back.
+    pub comments: Vec<Comment>,
+    /// Byte offset of the start of the current lexeme.
     start: usize,
+    /// Byte offset of the next unconsumed character.
     current: usize,
+    /// Line and column (in characters) of `current`.
     line: usize,
+    column: usize,
+    start_line: usize,
+    start_column: usize,
+    dialect: Dialect,
 }
 #[derive(Error, Debug)]
 pub enum ScanError {
-    #[error("Unexpected character {1} at line {0}")]
-    UnexpectedCharacter(usize, char),
-    #[error("Unterminated string at line {0}")]
-    UnterminatedString(usize),
-    #[error("Unexpected end of file at line {0}")]
-    UnexpectedEndOfFile(usize),
-    #[error("Unterminated comment at line {0}")]
-    UnterminatedComment(usize),
-    #[error("Invalid number at line {0}")]
-    InvalidNumber(usize, std::num::ParseFloatError),
+    #[error("Unexpected character {1} at {0}")]
+    UnexpectedCharacter(Span, char),
+    #[error("Unterminated string at {0}")]
+    UnterminatedString(Span),
+    #[error("Unexpected end of file at {0}")]
+    UnexpectedEndOfFile(Span),
+    #[error("Unterminated comment at {0}")]
+    UnterminatedComment(Span),
+    #[error("Invalid number '{1}' at {0}")]
+    InvalidNumber(Span, String),
+    #[error("Invalid escape sequence '{1}' at {0}")]
+    InvalidEscape(Span, String),
 }
 macro_rules! add_tok {
-    ($self:ident, $token_type:ident) => {{
-        $self.chomp(1);
-        $self.add_token(TokenType::$token_type, $self.line)
-    }};
+    ($self:ident, $token_type:ident) => {
+        $self.add_token(TokenType::$token_type)
+    };
     // To match stuff like !=  == >=
     ($self:ident, $token_type:ident, $token_alt:ident, $next:tt) => {{
-        let token_type = if $self.peek()? == $next {
-            $self.chomp(1);
+        let token_type = if $self.match_char($next) {
             TokenType::$token_alt
         } else {
             TokenType::$token_type
         };
-        $self.chomp(1);
-        $self.add_token(token_type, $self.line);
+        $self.add_token(token_type);
     }};
 }
 impl<'a> Scanner<'a> {
     pub fn new(source: &'a str) -> Self {
+        Self::with_dialect(source, Dialect::default())
+    }
+    pub fn with_dialect(source: &'a str, dialect: Dialect) -> Self {
         Self {
             source,
             tokens: Vec::new(),
+            errors: Vec::new(),
+            comments: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
+            dialect,
         }
     }
-    pub fn add_token(&mut self, token_type: TokenType, line: usize) {
-        self.tokens.push(Token::new(token_type, line));
+    /// Starts scanning at byte `offset`, so that spans index the whole
+    /// source, as when it holds several inputs one after another. Lines and
+    /// columns are still counted from 1 there.
+    pub fn starting_at(mut self, offset: usize) -> Self {
+        self.start = offset;
+        self.current = offset;
+        self
     }
-    pub fn scan_tokens(&mut self) -> Result<Vec<Token>, ScanError> {
+    /// Adds a token spanning from the start of the current lexeme up to
+    /// everything consumed so far.
+    pub fn add_token(&mut self, token_type: TokenType) {
+        let span = Span::new(self.start, self.current, self.start_line, self.start_column);
+        self.tokens.push(Token::new(token_type, span));
+    }
+    /// The span of the current lexeme.
+    fn lexeme_span(&self) -> Span {
+        Span::new(self.start, self.current, self.start_line, self.start_column)
+    }
+    fn mark_start(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
+    }
+    /// Scans the whole source. Lexical errors do not stop the scan: the
+    /// offending text becomes a placeholder token or is skipped, and the
+    /// error is recorded, so the tokens are always complete enough to be
+    /// parsed and checked for further errors.
+    pub fn scan_tokens(&mut self) -> (Vec<Token>, Vec<ScanError>) {
         while !self.is_at_end() {
-            // We are at the beginning of the next lexeme.
-            self.start = self.current;
-            self.scan_token()?;
+            if let Err(e) = self.scan_token() {
+                self.errors.push(e);
+            }
         }
-        self.tokens.push(Token::new(TokenType::Eof, self.line));
-        Ok(self.tokens.clone())
+        self.mark_start();
+        self.add_token(TokenType::Eof);
+        (self.tokens.clone(), std::mem::take(&mut self.errors))
     }
+    /// Scans the next token. On error the offending text has already been
+    /// consumed, so scanning can simply carry on.
     pub fn scan_token(&mut self) -> Result<(), ScanError> {
         self.skip_all()?;
-        let next = match self.source.chars().next() {
+        // We are at the beginning of the next lexeme.
+        self.mark_start();
+        let next = match self.advance() {
             Some(c) => c,
             None => return Ok(()),
         };
@@ -83,123 +163,297 @@ impl<'a> Scanner<'a> {
             '=' => add_tok!(self, Equal, EqualEqual, '='),
             '<' => add_tok!(self, Less, LessEqual, '='),
             '>' => add_tok!(self, Greater, GreaterEqual, '='),
-            '\n' => {
-                self.line += 1;
-                self.chomp(1);
-            }
             '0'..='9' => self.tok_num()?,
             '"' => self.tok_string()?,
-            'a'..='z' | 'A'..='Z' | '_' => self.tok_ident()?,
-            _ => return Err(ScanError::UnexpectedCharacter(self.line, next)),
+            'r' if self.at_raw_string() => self.tok_raw_string()?,
+            c if self.dialect.is_ident_start(c) => self.tok_ident(),
+            _ => {
+                // Keep a placeholder token so the parser does not report the gap again.
+                self.add_token(TokenType::Error(next));
+                return Err(ScanError::UnexpectedCharacter(self.lexeme_span(), next));
+            }
         }
         Ok(())
     }
     pub fn is_at_end(&self) -> bool {
-        if self.source.is_empty() {
-            true
+        self.current >= self.source.len()
+    }
+    /// Everything not consumed yet.
+    fn rest(&self) -> &'a str {
+        &self.source[self.current..]
+    }
+    /// The next character, without consuming it.
+    pub fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    /// The character after [`Scanner::peek`].
+    pub fn peek_next(&self) -> Option<char> {
+        self.rest().chars().nth(1)
+    }
+    /// Consumes one character. This is the only place the position moves, so
+    /// it is also the one place lines and columns are counted.
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.current += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.source.starts_with('\0')
+            self.column += 1;
         }
+        Some(c)
     }
-    pub fn peek(&self) -> Result<char, ScanError> {
-        self.source
-            .chars()
-            .nth(1)
-            .ok_or(ScanError::UnexpectedEndOfFile(self.line))
+    fn advance_while(&mut self, predicate: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&predicate) {
+            self.advance();
+        }
+    }
+    /// Consumes characters until `offset` bytes into the source.
+    fn advance_to(&mut self, offset: usize) {
+        while self.current < offset && self.advance().is_some() {}
+    }
+    fn match_char(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.advance();
+            true
+        } else {
+            false
+        }
     }
     fn tok_num(&mut self) -> Result<(), ScanError> {
-        let substr: String = self
-            .source
-            .chars()
-            .take_while(|c| c.is_ascii_digit())
-            .collect::<String>();
-        let num: f64 = substr
-            .parse()
-            .map_err(|e| ScanError::InvalidNumber(self.line, e))?;
-        self.add_token(TokenType::Number(num), self.line);
-        self.chomp(substr.len());
-        Ok(())
+        let literal = &self.source[self.start..];
+        let (len, num) = match self.dialect {
+            Dialect::Lox => {
+                let len = number_len(literal.as_bytes(), false);
+                (len, literal[..len].parse().ok())
+            }
+            Dialect::Extended => {
+                let len = number_len(literal.as_bytes(), true);
+                (len, parse_extended_number(&literal[..len]))
+            }
+        };
+        self.advance_to(self.start + len);
+        // Keep a placeholder token so the parser does not trip over the gap.
+        self.add_token(TokenType::Number(num.unwrap_or(f64::NAN)));
+        match num {
+            Some(_) => Ok(()),
+            None => Err(ScanError::InvalidNumber(
+                self.lexeme_span(),
+                literal[..len].to_string(),
+            )),
+        }
     }
+    /// Scans a string literal, translating escape sequences. A bad escape
+    /// is recorded but does not end the string. A backslash has to be
+    /// escaped itself, or written in a raw string.
     fn tok_string(&mut self) -> Result<(), ScanError> {
-        let substr: String = self
-            .source
-            .chars()
-            .skip(1)
-            .take_while(|c| *c != '"')
-            .collect();
-        let num_bytes = substr.len();
-        if self.source.chars().nth(num_bytes + 1) != Some('"') {
-            return Err(ScanError::UnterminatedString(self.line));
+        let mut contents = String::new();
+        loop {
+            match self.advance() {
+                // The rest of the file is swallowed by the string.
+                None => return Err(self.unterminated_string(1)),
+                Some('"') => break,
+                Some('\\') if self.peek().is_some() => match self.escape() {
+                    Ok(c) => contents.push(c),
+                    Err(e) => self.errors.push(e),
+                },
+                Some(c) => contents.push(c),
+            }
         }
-        self.chomp(substr.len() + 2);
-        self.line += substr.matches('\n').count();
-        self.add_token(TokenType::String(substr), self.line);
+        self.add_token(TokenType::String(contents));
         Ok(())
     }
-
-    fn tok_ident(&mut self) -> Result<(), ScanError> {
-        let substr: String = self
-            .source
-            .chars()
-            .take_while(|c| c.is_alphanumeric() || *c == '_')
-            .collect();
-        let num_bytes = substr.len();
-        let token_type = match KEYWORDS.get(substr.as_str()) {
+    /// Reads the rest of an escape sequence whose `\` was just consumed.
+    fn escape(&mut self) -> Result<char, ScanError> {
+        let start = (self.current - 1, self.line, self.column - 1);
+        let c = match self.advance() {
+            Some('n') => Some('\n'),
+            Some('t') => Some('\t'),
+            Some('"') => Some('"'),
+            Some('\\') => Some('\\'),
+            Some('u') => self.unicode_escape(),
+            _ => None,
+        };
+        c.ok_or_else(|| {
+            let span = Span::new(start.0, self.current, start.1, start.2);
+            ScanError::InvalidEscape(span, self.source[start.0..self.current].to_string())
+        })
+    }
+    /// `\u{...}` with one to six hex digits naming a Unicode scalar value.
+    fn unicode_escape(&mut self) -> Option<char> {
+        if !self.match_char('{') {
+            return None;
+        }
+        let digits_start = self.current;
+        self.advance_while(|c| c.is_ascii_hexdigit());
+        let digits = &self.source[digits_start..self.current];
+        if !self.match_char('}') || digits.is_empty() || digits.len() > 6 {
+            return None;
+        }
+        char::from_u32(u32::from_str_radix(digits, 16).ok()?)
+    }
+    /// Whether the `r` just consumed starts a raw string: `r"..."`, or
+    /// `r#"..."#` with any number of `#` to allow quotes inside.
+    fn at_raw_string(&self) -> bool {
+        self.rest().trim_start_matches('#').starts_with('"')
+    }
+    /// Scans a raw string. Nothing inside is special, so it can hold
+    /// backslashes and span several lines as written.
+    fn tok_raw_string(&mut self) -> Result<(), ScanError> {
+        let hashes = self.rest().len() - self.rest().trim_start_matches('#').len();
+        let closing = format!("\"{}", "#".repeat(hashes));
+        self.advance_to(self.current + hashes + 1);
+        let contents_start = self.current;
+        match self.rest().find(&closing) {
+            Some(len) => {
+                self.advance_to(contents_start + len + closing.len());
+                let contents = &self.source[contents_start..contents_start + len];
+                self.add_token(TokenType::String(contents.to_string()));
+                Ok(())
+            }
+            None => {
+                self.advance_to(self.source.len());
+                Err(self.unterminated_string(hashes + 2))
+            }
+        }
+    }
+    /// An unterminated string error pointing at the opening delimiter, which
+    /// is `len` bytes long.
+    fn unterminated_string(&self, len: usize) -> ScanError {
+        ScanError::UnterminatedString(Span::new(
+            self.start,
+            self.start + len,
+            self.start_line,
+            self.start_column,
+        ))
+    }
+    fn tok_ident(&mut self) {
+        let dialect = self.dialect;
+        self.advance_while(|c| dialect.is_ident_continue(c));
+        let lexeme = &self.source[self.start..self.current];
+        let token_type = match KEYWORDS.get(lexeme) {
             Some(token_type) => token_type.clone(),
-            None => TokenType::Identifier(substr),
+            None => TokenType::Identifier(lexeme.to_string()),
         };
-        self.add_token(token_type, self.line);
-        self.chomp(num_bytes);
-        Ok(())
+        self.add_token(token_type);
     }
 
     fn skip_all(&mut self) -> Result<(), ScanError> {
-        self.skip_whitespace();
-        self.skip_comments()
+        loop {
+            let before = self.current;
+            self.skip_whitespace();
+            self.scan_comment()?;
+            if self.current == before {
+                return Ok(());
+            }
+        }
     }
     fn skip_whitespace(&mut self) {
-        match self
-            .source
-            .chars()
-            .take_while(|c| c.is_whitespace())
-            .count()
-        {
-            0 => {}
-            n => self.chomp(n),
+        self.advance_while(char::is_whitespace);
+    }
+    /// Records a comment, if one starts here.
+    fn scan_comment(&mut self) -> Result<(), ScanError> {
+        self.mark_start();
+        if self.rest().starts_with("//") {
+            self.advance_while(|c| c != '\n');
+        } else if self.rest().starts_with("/*") {
+            self.scan_block_comment()?;
+        } else {
+            return Ok(());
         }
+        let span = self.lexeme_span();
+        debug!("Comment: {}", &self.source[span.start..span.end]);
+        self.comments.push(Comment {
+            text: self.source[span.start..span.end].to_string(),
+            span,
+        });
+        Ok(())
     }
-    fn skip_comments(&mut self) -> Result<(), ScanError> {
-        let pairs = [("//", '\n')];
-
-        for &(pattern, matcher) in &pairs {
-            if self.source.starts_with(pattern) {
-                let leftovers: String = self.source.chars().take_while(|c| *c != matcher).collect();
-                let num_bytes = leftovers.len();
-                debug!("Skipping comment: {}", leftovers);
-                if num_bytes == 0 {
-                    return Err(ScanError::UnterminatedComment(self.line));
-                }
-                self.current += num_bytes;
-                self.chomp(num_bytes);
-                if matcher == '\n' {
-                    self.line += 1;
-                } else {
-                    self.line += leftovers.matches('\n').count();
-                }
+    /// Consumes a `/* ... */` comment. Block comments nest, so commenting out
+    /// code that already contains one works as expected.
+    fn scan_block_comment(&mut self) -> Result<(), ScanError> {
+        let opener = Span::new(self.current, self.current + 2, self.line, self.column);
+        self.advance_to(self.current + 2);
+        let mut depth = 1;
+        while depth > 0 {
+            if self.rest().starts_with("/*") {
+                depth += 1;
+                self.advance_to(self.current + 2);
+            } else if self.rest().starts_with("*/") {
+                depth -= 1;
+                self.advance_to(self.current + 2);
+            } else if self.advance().is_none() {
+                return Err(ScanError::UnterminatedComment(opener));
             }
         }
         Ok(())
     }
-
-    fn chomp(&mut self, num_bytes: usize) {
-        debug!(
-            "Chomping {} bytes from string of size {}",
-            num_bytes,
-            self.source.len()
-        );
-        self.source = &self.source[num_bytes..];
-        self.current += num_bytes;
+}
+/// Length of the number literal at the start of `bytes`. In the extended
+/// dialect every trailing identifier character is swallowed as well, so that
+/// `0xfg` or `1e` are reported whole instead of splitting into two tokens.
+fn number_len(bytes: &[u8], extended: bool) -> usize {
+    let digits = |from: usize| {
+        from + bytes[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit() || (extended && **b == b'_'))
+            .count()
+    };
+    if extended && (bytes.starts_with(b"0x") || bytes.starts_with(b"0X")) {
+        return 2 + bytes[2..]
+            .iter()
+            .take_while(|b| b.is_ascii_alphanumeric() || **b == b'_')
+            .count();
+    }
+    let mut len = digits(0);
+    // The fractional part needs a digit after the dot, so `1.foo()` still
+    // scans as a method call on `1`.
+    if bytes.get(len) == Some(&b'.') && bytes.get(len + 1).is_some_and(u8::is_ascii_digit) {
+        len = digits(len + 1);
+    }
+    if !extended {
+        return len;
+    }
+    if matches!(bytes.get(len), Some(b'e' | b'E')) {
+        len += 1;
+        if matches!(bytes.get(len), Some(b'+' | b'-')) {
+            len += 1;
+        }
+    }
+    len + bytes[len..]
+        .iter()
+        .take_while(|b| b.is_ascii_alphanumeric() || **b == b'_')
+        .count()
+}
+/// Parses a literal of the extended dialect, returning `None` if it is
+/// malformed. `_` is only allowed between two digits.
+fn parse_extended_number(lexeme: &str) -> Option<f64> {
+    let separators_ok =
+        |digits: &str| !digits.starts_with('_') && !digits.ends_with('_') && !digits.contains("__");
+    if let Some(hex) = lexeme
+        .strip_prefix("0x")
+        .or_else(|| lexeme.strip_prefix("0X"))
+    {
+        if hex.is_empty() || !separators_ok(hex) {
+            return None;
+        }
+        return hex
+            .chars()
+            .filter(|c| *c != '_')
+            .try_fold(0.0, |acc, c| Some(acc * 16.0 + c.to_digit(16)? as f64));
     }
+    let (mantissa, exponent) = match lexeme.find(['e', 'E']) {
+        Some(i) => (&lexeme[..i], Some(&lexeme[i + 1..])),
+        None => (lexeme, None),
+    };
+    let mut groups = mantissa
+        .split('.')
+        .chain(exponent.map(|e| e.strip_prefix(['+', '-']).unwrap_or(e)));
+    let all_digits = |group: &str| group.chars().all(|c| c.is_ascii_digit() || c == '_');
+    if !groups.all(|group| !group.is_empty() && all_digits(group) && separators_ok(group)) {
+        return None;
+    }
+    lexeme.replace('_', "").parse().ok()
 }
 #[cfg(test)]
 mod test {
@@ -209,12 +463,13 @@ mod test {
         #[test]
         fn $test_name() {
             let mut scanner = Scanner::new($lexeme);
-            let tokens = scanner.scan_tokens().unwrap();
-            let mut base_token = Vec::new();
-            $(
-                base_token.push(Token::new($x, 1));
-            )*
-            base_token.push(Token::new(TokenType::Eof, 1));
+            let (tokens, errors) = scanner.scan_tokens();
+            assert!(errors.is_empty(), "{:?}", errors);
+            let tokens: Vec<_> = tokens
+                .into_iter()
+                .map(|token| token.token_type)
+                .collect();
+            let base_token = vec![$($x,)* TokenType::Eof];
             println!("Tokens: {:?}", tokens);
             println!("Base: {:?}", base_token);
             assert_eq!(do_vecs_match(&tokens, &base_token), true);
@@ -244,7 +499,303 @@ mod test {
         TokenType::Plus,
         TokenType::Number(2.0)
     );
-    fn do_vecs_match<T: PartialEq>(a: &Vec<T>, b: &Vec<T>) -> bool {
+    lexer_test!(test_decimal, "12.75", TokenType::Number(12.75));
+    lexer_test!(
+        test_trailing_dot,
+        "1.",
+        TokenType::Number(1.0),
+        TokenType::Dot
+    );
+    lexer_test!(
+        test_leading_dot,
+        ".5",
+        TokenType::Dot,
+        TokenType::Number(5.0)
+    );
+    lexer_test!(
+        test_lox_has_no_exponent,
+        "1e3",
+        TokenType::Number(1.0),
+        TokenType::Identifier("e3".into())
+    );
+    fn scan_extended(source: &str) -> Result<Vec<Token>, ScanError> {
+        match Scanner::with_dialect(source, Dialect::Extended).scan_tokens() {
+            (tokens, errors) if errors.is_empty() => Ok(tokens),
+            (_, mut errors) => Err(errors.remove(0)),
+        }
+    }
+    #[test]
+    fn test_extended_numbers() {
+        for (source, expected) in [
+            ("1e3", 1000.0),
+            ("2.5E-1", 0.25),
+            ("1_000_000", 1_000_000.0),
+            ("0xff", 255.0),
+            ("0xdead_beef", 3_735_928_559.0),
+        ] {
+            match scan_extended(source) {
+                Ok(tokens) => assert_eq!(tokens[0].token_type, TokenType::Number(expected)),
+                Err(e) => panic!("{}: {}", source, e),
+            }
+        }
+    }
+    #[test]
+    fn test_invalid_extended_numbers() {
+        for source in ["1_", "1__0", "1e", "1e+", "0x", "0X_1", "0xfg", "12abc"] {
+            match scan_extended(source) {
+                Err(ScanError::InvalidNumber(span, lexeme)) => {
+                    assert_eq!(lexeme, source);
+                    assert_eq!((span.start, span.end), (0, source.len()));
+                }
+                other => panic!("{}: {:?}", source, other),
+            }
+        }
+    }
+    #[test]
+    fn test_spans() {
+        let source = "var x = 10;\n  // comment\n  print \"a\nb\" + x;";
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        let spans: Vec<_> = tokens
+            .iter()
+            .map(|t| {
+                (
+                    &source[t.span.start..t.span.end],
+                    t.span.line,
+                    t.span.column,
+                )
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("var", 1, 1),
+                ("x", 1, 5),
+                ("=", 1, 7),
+                ("10", 1, 9),
+                (";", 1, 11),
+                ("print", 3, 3),
+                ("\"a\nb\"", 3, 9),
+                ("+", 4, 4),
+                ("x", 4, 6),
+                (";", 4, 7),
+                ("", 4, 8),
+            ]
+        );
+    }
+    #[test]
+    fn test_starting_at_an_offset() {
+        let source = "print 1;\nprint 2;";
+        let (tokens, _) = Scanner::new(source).starting_at(9).scan_tokens();
+        let print = &tokens[0];
+        assert_eq!(print.token_type, TokenType::Print);
+        assert_eq!(print.span, Span::new(9, 14, 1, 1));
+        assert_eq!(tokens.len(), 4);
+    }
+    #[test]
+    fn test_error_position() {
+        match &Scanner::new("1 +\n  @").scan_tokens().1[..] {
+            [ScanError::UnexpectedCharacter(span, '@')] => {
+                assert_eq!(*span, Span::new(6, 7, 2, 3));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+    #[test]
+    fn test_scanning_continues_after_errors() {
+        let (tokens, errors) = Scanner::new("var @a = 1 # 2;\nprint \"oops;").scan_tokens();
+        let kinds: Vec<_> = tokens.into_iter().map(|t| t.token_type).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Var,
+                TokenType::Error('@'),
+                TokenType::Identifier("a".into()),
+                TokenType::Equal,
+                TokenType::Number(1.0),
+                TokenType::Error('#'),
+                TokenType::Number(2.0),
+                TokenType::Semicolon,
+                TokenType::Print,
+                TokenType::Eof,
+            ]
+        );
+        match &errors[..] {
+            [ScanError::UnexpectedCharacter(a, '@'), ScanError::UnexpectedCharacter(b, '#'), ScanError::UnterminatedString(c)] =>
+            {
+                assert_eq!((a.line, a.column), (1, 5));
+                assert_eq!((b.line, b.column), (1, 12));
+                assert_eq!((c.line, c.column), (2, 7));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+    lexer_test!(
+        test_utf8_string,
+        "\"héllo, 世界 🦀\"",
+        TokenType::String("héllo, 世界 🦀".into())
+    );
+    lexer_test!(
+        test_utf8_comment,
+        "// コメント 🎉 ünïcödé\n1 // 🦀",
+        TokenType::Number(1.0)
+    );
+    lexer_test!(
+        test_multibyte_characters_around_operators,
+        "\"ä\"+\"ö\"",
+        TokenType::String("ä".into()),
+        TokenType::Plus,
+        TokenType::String("ö".into())
+    );
+    #[test]
+    fn test_columns_count_characters() {
+        let source = "print \"日本\" + x;";
+        let (tokens, _) = Scanner::new(source).scan_tokens();
+        let columns: Vec<_> = tokens.iter().map(|t| t.span.column).collect();
+        assert_eq!(columns, vec![1, 7, 12, 14, 15, 16]);
+        assert_eq!(
+            &source[tokens[1].span.start..tokens[1].span.end],
+            "\"日本\""
+        );
+    }
+    #[test]
+    fn test_unexpected_multibyte_character() {
+        match &Scanner::new("1 € 2").scan_tokens().1[..] {
+            [ScanError::UnexpectedCharacter(span, '€')] => {
+                assert_eq!(*span, Span::new(2, 5, 1, 3));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+    #[test]
+    fn test_unicode_identifiers() {
+        let (tokens, errors) =
+            Scanner::with_dialect("var café = π_2 + _ñ;", Dialect::Extended).scan_tokens();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let names: Vec<_> = tokens
+            .into_iter()
+            .filter_map(|t| match t.token_type {
+                TokenType::Identifier(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["café", "π_2", "_ñ"]);
+        // Plain Lox keeps identifiers ASCII.
+        let (_, errors) = Scanner::new("café").scan_tokens();
+        assert!(matches!(
+            errors[..],
+            [ScanError::UnexpectedCharacter(_, 'é')]
+        ));
+    }
+    lexer_test!(
+        test_escapes,
+        r#""a\tb\nc \"q\" \\ \u{48}\u{1F980}""#,
+        TokenType::String("a\tb\nc \"q\" \\ H🦀".into())
+    );
+    lexer_test!(
+        test_raw_string,
+        r##"r"C:\dir\n" r#"say "hi""#"##,
+        TokenType::String("C:\\dir\\n".into()),
+        TokenType::String("say \"hi\"".into())
+    );
+    lexer_test!(
+        test_r_identifier,
+        "r + r",
+        TokenType::Identifier("r".into()),
+        TokenType::Plus,
+        TokenType::Identifier("r".into())
+    );
+    #[test]
+    fn test_lone_backslashes_are_invalid_escapes() {
+        match &Scanner::new(r#""C:\path""#).scan_tokens().1[..] {
+            [ScanError::InvalidEscape(span, text)] => {
+                assert_eq!((span.column, text.as_str()), (4, "\\p"))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+    #[test]
+    fn test_invalid_escapes() {
+        let (tokens, errors) = Scanner::new(r#""\q \u{} \u{110000} \u41 ok" 1"#).scan_tokens();
+        let escapes: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                ScanError::InvalidEscape(span, text) => (span.column, text.as_str()),
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            escapes,
+            vec![(2, "\\q"), (5, "\\u{}"), (10, "\\u{110000}"), (21, "\\u")]
+        );
+        // The string still ends at its closing quote.
+        assert_eq!(tokens[0].token_type, TokenType::String("   41 ok".into()));
+        assert_eq!(tokens[1].token_type, TokenType::Number(1.0));
+    }
+    #[test]
+    fn test_multi_line_raw_string() {
+        let (tokens, errors) = Scanner::new("r#\"one\n\"two\"\n\"#\nx").scan_tokens();
+        assert!(errors.is_empty());
+        assert_eq!(
+            tokens[0].token_type,
+            TokenType::String("one\n\"two\"\n".into())
+        );
+        assert_eq!((tokens[1].span.line, tokens[1].span.column), (4, 1));
+    }
+    #[test]
+    fn test_unterminated_raw_string() {
+        match &Scanner::new("x\nr##\"abc\"#").scan_tokens().1[..] {
+            [ScanError::UnterminatedString(span)] => assert_eq!(*span, Span::new(2, 6, 2, 1)),
+            other => panic!("{:?}", other),
+        }
+    }
+    lexer_test!(
+        test_block_comment,
+        "1 /* one */ + /**/ 2",
+        TokenType::Number(1.0),
+        TokenType::Plus,
+        TokenType::Number(2.0)
+    );
+    lexer_test!(
+        test_nested_block_comment,
+        "/* outer /* inner */ still a comment */ x /*/ */",
+        TokenType::Identifier("x".into())
+    );
+    #[test]
+    fn test_lines_across_block_comments() {
+        let (tokens, errors) = Scanner::new("/* a\n  /* b\n */\n*/ x\n/* 日本 */ y").scan_tokens();
+        assert!(errors.is_empty());
+        let positions: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.span.line, t.span.column))
+            .collect();
+        assert_eq!(positions, vec![(4, 4), (5, 10), (5, 11)]);
+    }
+    #[test]
+    fn test_unterminated_block_comment() {
+        let (tokens, errors) = Scanner::new("x\n  /* a /* b */ c\n").scan_tokens();
+        assert_eq!(tokens.len(), 2);
+        match &errors[..] {
+            [ScanError::UnterminatedComment(span)] => assert_eq!(*span, Span::new(4, 6, 2, 3)),
+            other => panic!("{:?}", other),
+        }
+    }
+    #[test]
+    fn test_comments_are_kept() {
+        let mut scanner = Scanner::new("// one\nx /* two\n */ y // three");
+        let (tokens, errors) = scanner.scan_tokens();
+        assert!(errors.is_empty());
+        assert_eq!(tokens.len(), 3);
+        let comments: Vec<_> = scanner
+            .comments
+            .iter()
+            .map(|c| (c.text.as_str(), c.span.line, c.span.column))
+            .collect();
+        assert_eq!(
+            comments,
+            vec![("// one", 1, 1), ("/* two\n */", 2, 3), ("// three", 3, 7)]
+        );
+    }
+    fn do_vecs_match<T: PartialEq>(a: &[T], b: &[T]) -> bool {
         let matching = a.iter().zip(b.iter()).filter(|&(a, b)| a == b).count();
         matching == a.len() && matching == b.len()
     }
@@ -1,7 +1,6 @@
 use std::fmt::Display;
 
 use phf::phf_map;
-use thiserror::Error;
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     LeftParen,
@@ -42,45 +41,55 @@ pub enum TokenType {
     True,
     Var,
     While,
+    /// A character the scanner could not make sense of. It has already been
+    /// reported, so the parser only uses it to recover.
+    Error(char),
     Eof,
 }
-#[derive(Error, Debug)]
-pub enum TypeError {
-    #[error("Invalid Convertion from {0} to {1}")]
-    InvalidConvertion(String, String),
-}
-impl TryInto<bool> for TokenType {
-    type Error = TypeError;
-    fn try_into(self) -> Result<bool, Self::Error> {
+impl TokenType {
+    /// The name of the variant, without any payload, e.g. `"Identifier"`.
+    pub fn kind(&self) -> &'static str {
         match self {
-            TokenType::True => Ok(true),
-            TokenType::False => Ok(false),
-            TokenType::Nil => Ok(false),
-            _ => Ok(true),
-        }
-    }
-}
-impl TryFrom<bool> for TokenType {
-    type Error = TypeError;
-    fn try_from(value: bool) -> Result<Self, Self::Error> {
-        if value {
-            Ok(TokenType::True)
-        } else {
-            Ok(TokenType::False)
-        }
-    }
-}
-impl TryInto<f64> for TokenType {
-    type Error = TypeError;
-    fn try_into(self) -> Result<f64, Self::Error> {
-        match self {
-            TokenType::Number(n) => Ok(n),
-            TokenType::True => Ok(1.0),
-            TokenType::False => Ok(0.0),
-            _ => Err(TypeError::InvalidConvertion(
-                self.to_string(),
-                "f64".to_string(),
-            )),
+            TokenType::LeftParen => "LeftParen",
+            TokenType::RightParen => "RightParen",
+            TokenType::LeftBrace => "LeftBrace",
+            TokenType::RightBrace => "RightBrace",
+            TokenType::Comma => "Comma",
+            TokenType::Dot => "Dot",
+            TokenType::Minus => "Minus",
+            TokenType::Plus => "Plus",
+            TokenType::Semicolon => "Semicolon",
+            TokenType::Slash => "Slash",
+            TokenType::Star => "Star",
+            TokenType::Bang => "Bang",
+            TokenType::BangEqual => "BangEqual",
+            TokenType::Equal => "Equal",
+            TokenType::EqualEqual => "EqualEqual",
+            TokenType::Greater => "Greater",
+            TokenType::GreaterEqual => "GreaterEqual",
+            TokenType::Less => "Less",
+            TokenType::LessEqual => "LessEqual",
+            TokenType::Identifier(_) => "Identifier",
+            TokenType::String(_) => "String",
+            TokenType::Number(_) => "Number",
+            TokenType::And => "And",
+            TokenType::Class => "Class",
+            TokenType::Else => "Else",
+            TokenType::False => "False",
+            TokenType::Fun => "Fun",
+            TokenType::For => "For",
+            TokenType::If => "If",
+            TokenType::Nil => "Nil",
+            TokenType::Or => "Or",
+            TokenType::Print => "Print",
+            TokenType::Return => "Return",
+            TokenType::Super => "Super",
+            TokenType::This => "This",
+            TokenType::True => "True",
+            TokenType::Var => "Var",
+            TokenType::While => "While",
+            TokenType::Error(_) => "Error",
+            TokenType::Eof => "Eof",
         }
     }
 }
@@ -125,18 +134,62 @@ impl Display for TokenType {
             TokenType::True => write!(f, "true"),
             TokenType::Var => write!(f, "var"),
             TokenType::While => write!(f, "while"),
+            TokenType::Error(c) => write!(f, "{}", c),
             TokenType::Eof => write!(f, "EOF"),
         }
     }
 }
+/// A range of source text. `start` and `end` are byte offsets, `line` and
+/// `column` (both 1-based) locate `start` for humans.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+    /// The smallest span covering both `self` and `other`, which must not
+    /// start before `self`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: self.end.max(other.end),
+            ..self
+        }
+    }
+}
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+/// A comment, kept by the scanner beside the tokens. `text` includes the
+/// delimiters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
-    pub line: usize,
+    pub span: Span,
 }
 impl Token {
-    pub fn new(token_type: TokenType, line: usize) -> Self {
-        Self { token_type, line }
+    pub fn new(token_type: TokenType, span: Span) -> Self {
+        Self { token_type, span }
+    }
+    /// The source text of the token, e.g. the bare name of an identifier.
+    pub fn lexeme(&self) -> String {
+        self.token_type.to_string()
     }
 }
 impl Display for Token {
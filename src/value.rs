@@ -0,0 +1,123 @@
+use std::{cell::RefCell, fmt::Display, rc::Rc};
+
+use crate::{
+    class::{LoxClass, LoxInstance},
+    function::LoxFunction,
+    native::NativeFunction,
+    tokens::TokenType,
+};
+
+/// A runtime Lox value. Strings and objects are reference counted, so cloning
+/// a value never copies its contents.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+}
+impl Value {
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+        }
+    }
+    /// Converts a literal token into the value it denotes.
+    pub fn from_literal(token_type: &TokenType) -> Option<Value> {
+        match token_type {
+            TokenType::Nil => Some(Value::Nil),
+            TokenType::True => Some(Value::Bool(true)),
+            TokenType::False => Some(Value::Bool(false)),
+            TokenType::Number(n) => Some(Value::Number(*n)),
+            TokenType::String(s) => Some(Value::String(s.as_str().into())),
+            _ => None,
+        }
+    }
+}
+// Values of different types are never equal; objects compare by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Function(function) => write!(f, "<fn {}>", function.name()),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name()),
+            Value::Class(class) => write!(f, "{}", class.name()),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class().name()),
+        }
+    }
+}
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.into())
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn truthiness() {
+        assert!(!Value::Nil.is_truthy());
+        assert!(!Value::Bool(false).is_truthy());
+        assert!(Value::Bool(true).is_truthy());
+        assert!(Value::Number(0.0).is_truthy());
+        assert!(Value::from("").is_truthy());
+    }
+    #[test]
+    fn equality() {
+        assert_eq!(Value::Nil, Value::Nil);
+        assert_eq!(Value::from("a"), Value::from("a"));
+        assert_eq!(Value::from(1.0), Value::from(1.0));
+        assert_ne!(Value::Nil, Value::Bool(false));
+        assert_ne!(Value::from("1"), Value::from(1.0));
+    }
+    #[test]
+    fn display() {
+        assert_eq!(Value::Nil.to_string(), "nil");
+        assert_eq!(Value::from(true).to_string(), "true");
+        assert_eq!(Value::from(3.0).to_string(), "3");
+        assert_eq!(Value::from(2.5).to_string(), "2.5");
+        assert_eq!(Value::from("hi").to_string(), "hi");
+    }
+}
@@ -0,0 +1,498 @@
+use std::{cell::RefCell, collections::HashMap, mem, rc::Rc};
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    class::{LoxClass, LoxInstance},
+    environment::{Environment, Globals},
+    function::{Code, LoxCallable, LoxFunction},
+    interpreter::InterpreterError,
+    native,
+    tokens::Span,
+    value::Value,
+};
+
+/// How deep calls may nest before the program is stopped, on either backend.
+pub(crate) const FRAMES_MAX: usize = 10_000;
+
+type VmResult<T> = Result<T, InterpreterError>;
+
+/// A function being run.
+struct CallFrame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    /// The environment to go back to when the function returns.
+    caller_environment: Environment,
+    /// The instance an initializer returns, whatever its body returns.
+    instance: Option<Value>,
+}
+impl CallFrame {
+    fn read_u8(&mut self) -> u8 {
+        self.ip += 1;
+        self.chunk.code[self.ip - 1]
+    }
+    fn read_u16(&mut self) -> u16 {
+        self.ip += 2;
+        self.chunk.read_u16(self.ip - 2)
+    }
+    fn read_usize(&mut self) -> usize {
+        self.read_u16() as usize
+    }
+    fn read_name(&mut self) -> Rc<str> {
+        let index = self.read_usize();
+        match &self.chunk.constants[index] {
+            Value::String(name) => name.clone(),
+            _ => unreachable!("names are string constants"),
+        }
+    }
+}
+
+/// A stack machine running chunks made by the [`Compiler`](crate::compiler::Compiler).
+/// It behaves like the tree-walking [`Interpreter`](crate::interpreter::Interpreter),
+/// down to its runtime errors, and shares its values and scopes.
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    environment: Environment,
+    globals: Globals,
+}
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Self::default();
+        for native in native::standard_library() {
+            vm.globals.define_native(native);
+        }
+        vm
+    }
+    pub fn globals(&self) -> &Globals {
+        &self.globals
+    }
+    /// The globals table, for resolving and compiling code before it is run
+    /// here.
+    pub fn globals_mut(&mut self) -> &mut Globals {
+        &mut self.globals
+    }
+    pub fn interpret(&mut self, chunk: Chunk) -> VmResult<()> {
+        let frame = CallFrame {
+            chunk: Rc::new(chunk),
+            ip: 0,
+            caller_environment: self.environment.clone(),
+            instance: None,
+        };
+        let result = self.run(frame);
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.environment = Environment::new();
+        }
+        result
+    }
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("compiled code keeps the stack balanced")
+    }
+    fn peek(&self) -> &Value {
+        self.stack
+            .last()
+            .expect("compiled code keeps the stack balanced")
+    }
+    fn error<T>(message: impl Into<String>, span: Span) -> VmResult<T> {
+        Err(InterpreterError::RuntimeError(message.into(), span))
+    }
+    /// Pops both operands of a binary instruction, blaming the offending one.
+    /// Spans are only looked up on error, to keep arithmetic cheap.
+    fn pop_numbers(&mut self, frame: &CallFrame, offset: usize) -> VmResult<(f64, f64)> {
+        let right = self.pop();
+        let left = self.pop();
+        match (left, right) {
+            (Value::Number(l), Value::Number(r)) => Ok((l, r)),
+            (Value::Number(_), _) => Vm::error(
+                "Right operand must be a number.",
+                frame.chunk.operand_spans(offset).1,
+            ),
+            _ => Vm::error(
+                "Left operand must be a number.",
+                frame.chunk.operand_spans(offset).0,
+            ),
+        }
+    }
+    fn bind_method(
+        class: &LoxClass,
+        instance: Value,
+        name: &str,
+        frame: &CallFrame,
+        offset: usize,
+    ) -> VmResult<Value> {
+        match class.find_method(name) {
+            Some(method) => Ok(Value::Function(Rc::new(method.bind(instance)))),
+            None => Vm::error(
+                format!("Undefined property '{}'.", name),
+                frame.chunk.span_at(offset),
+            ),
+        }
+    }
+    /// The span of the call at `offset`, for an error.
+    fn call_span(frame: &CallFrame, offset: usize) -> Span {
+        frame.chunk.operand_spans(offset).1
+    }
+    fn check_arity(arity: usize, count: usize, frame: &CallFrame, offset: usize) -> VmResult<()> {
+        if arity != count {
+            return Vm::error(
+                format!("Expected {} arguments but got {}.", arity, count),
+                Vm::call_span(frame, offset),
+            );
+        }
+        Ok(())
+    }
+    /// Calls the value below the top `count` values of the stack, which are
+    /// its arguments.
+    fn call_value(&mut self, frame: &mut CallFrame, count: usize, offset: usize) -> VmResult<()> {
+        let arguments = self.stack.split_off(self.stack.len() - count);
+        match self.pop() {
+            Value::Function(function) => {
+                Vm::check_arity(function.arity(), count, frame, offset)?;
+                let instance = function
+                    .is_initializer()
+                    .then(|| function.closure().get_at(0, 0).unwrap_or(Value::Nil));
+                self.call(frame, &function, arguments, instance, offset)
+            }
+            Value::Native(native) => {
+                Vm::check_arity(native.arity(), count, frame, offset)?;
+                let value = native.invoke(&arguments).map_err(|message| {
+                    InterpreterError::RuntimeError(message, Vm::call_span(frame, offset))
+                })?;
+                self.stack.push(value);
+                Ok(())
+            }
+            Value::Class(class) => {
+                let instance =
+                    Value::Instance(Rc::new(RefCell::new(LoxInstance::new(class.clone()))));
+                Vm::check_arity(class.arity(), count, frame, offset)?;
+                match class.find_method("init") {
+                    Some(initializer) => {
+                        let initializer = initializer.bind(instance.clone());
+                        self.call(frame, &initializer, arguments, Some(instance), offset)
+                    }
+                    None => {
+                        self.stack.push(instance);
+                        Ok(())
+                    }
+                }
+            }
+            _ => Vm::error(
+                "Can only call functions and classes.",
+                frame.chunk.operand_spans(offset).0,
+            ),
+        }
+    }
+    /// Enters `function` from the call at `offset`, leaving the caller's
+    /// frame on the frame stack.
+    fn call(
+        &mut self,
+        frame: &mut CallFrame,
+        function: &LoxFunction,
+        arguments: Vec<Value>,
+        instance: Option<Value>,
+        offset: usize,
+    ) -> VmResult<()> {
+        let Code::Bytecode { chunk, .. } = function.code() else {
+            unreachable!("the VM only creates compiled functions");
+        };
+        if self.frames.len() >= FRAMES_MAX {
+            return Vm::error("Stack overflow.", Vm::call_span(frame, offset));
+        }
+        let mut environment = function.closure().clone();
+        environment.enter_scope();
+        // Parameters take the first slots of the call's scope, in order.
+        for argument in arguments {
+            environment.define(argument);
+        }
+        let callee = CallFrame {
+            chunk: chunk.clone(),
+            ip: 0,
+            caller_environment: mem::replace(&mut self.environment, environment),
+            instance,
+        };
+        self.frames.push(mem::replace(frame, callee));
+        Ok(())
+    }
+    fn run(&mut self, mut frame: CallFrame) -> VmResult<()> {
+        loop {
+            let offset = frame.ip;
+            let op = OpCode::from_byte(frame.read_u8()).expect("compiled code is well formed");
+            match op {
+                OpCode::Constant => {
+                    let index = frame.read_usize();
+                    let value = frame.chunk.constants[index].clone();
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let (depth, slot) = (frame.read_usize(), frame.read_usize());
+                    match self.environment.get_at(depth, slot) {
+                        Some(value) => self.stack.push(value),
+                        None => return Vm::undefined_local(&frame, offset),
+                    }
+                }
+                OpCode::SetLocal => {
+                    let (depth, slot) = (frame.read_usize(), frame.read_usize());
+                    let value = self.peek().clone();
+                    if self.environment.assign_at(depth, slot, value).is_none() {
+                        return Vm::undefined_local(&frame, offset);
+                    }
+                }
+                OpCode::DefineLocal => {
+                    let value = self.pop();
+                    self.environment.define(value);
+                }
+                OpCode::GetGlobal => {
+                    let index = frame.read_usize();
+                    match self.globals.get(index) {
+                        Some(value) => self.stack.push(value),
+                        None => return self.undefined_global(index, &frame, offset),
+                    }
+                }
+                OpCode::SetGlobal => {
+                    let index = frame.read_usize();
+                    let value = self.peek().clone();
+                    if self.globals.assign(index, value).is_none() {
+                        return self.undefined_global(index, &frame, offset);
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let index = frame.read_usize();
+                    let value = self.pop();
+                    self.globals.define(index, value);
+                }
+                OpCode::GetProperty => {
+                    let name = frame.read_name();
+                    let object = self.pop();
+                    let Value::Instance(instance) = &object else {
+                        return Vm::error(
+                            "Only instances have properties.",
+                            frame.chunk.span_at(offset),
+                        );
+                    };
+                    let field = instance.borrow().get_field(&name);
+                    let value = match field {
+                        Some(value) => value,
+                        None => {
+                            let class = instance.borrow().class().clone();
+                            Vm::bind_method(&class, object, &name, &frame, offset)?
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty => {
+                    let name = frame.read_name();
+                    let value = self.pop();
+                    let Value::Instance(instance) = self.pop() else {
+                        return Vm::error(
+                            "Only instances have fields.",
+                            frame.chunk.span_at(offset),
+                        );
+                    };
+                    instance
+                        .borrow_mut()
+                        .set_field(name.to_string(), value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = frame.read_name();
+                    let superclass = self.pop();
+                    let instance = self.pop();
+                    let Value::Class(superclass) = superclass else {
+                        return Vm::error(
+                            "Can't use 'super' in a class with no superclass.",
+                            frame.chunk.span_at(offset),
+                        );
+                    };
+                    let method = Vm::bind_method(&superclass, instance, &name, &frame, offset)?;
+                    self.stack.push(method);
+                }
+                OpCode::Equal => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Bool(left == right));
+                }
+                OpCode::Greater => {
+                    let (l, r) = self.pop_numbers(&frame, offset)?;
+                    self.stack.push(Value::Bool(l > r));
+                }
+                OpCode::GreaterEqual => {
+                    let (l, r) = self.pop_numbers(&frame, offset)?;
+                    self.stack.push(Value::Bool(l >= r));
+                }
+                OpCode::Less => {
+                    let (l, r) = self.pop_numbers(&frame, offset)?;
+                    self.stack.push(Value::Bool(l < r));
+                }
+                OpCode::LessEqual => {
+                    let (l, r) = self.pop_numbers(&frame, offset)?;
+                    self.stack.push(Value::Bool(l <= r));
+                }
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = match (left, right) {
+                        (Value::Number(l), Value::Number(r)) => Value::Number(l + r),
+                        (Value::String(l), Value::String(r)) => {
+                            Value::String(format!("{}{}", l, r).into())
+                        }
+                        _ => {
+                            return Vm::error(
+                                "Operands must be two numbers or two strings.",
+                                frame.chunk.span_at(offset),
+                            )
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Subtract => {
+                    let (l, r) = self.pop_numbers(&frame, offset)?;
+                    self.stack.push(Value::Number(l - r));
+                }
+                OpCode::Multiply => {
+                    let (l, r) = self.pop_numbers(&frame, offset)?;
+                    self.stack.push(Value::Number(l * r));
+                }
+                OpCode::Divide => {
+                    let (l, r) = self.pop_numbers(&frame, offset)?;
+                    self.stack.push(Value::Number(l / r));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.pop() {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    _ => {
+                        return Vm::error("Operand must be a number.", frame.chunk.span_at(offset))
+                    }
+                },
+                OpCode::Print => println!("{}", self.pop()),
+                OpCode::Jump => {
+                    let distance = frame.read_usize();
+                    frame.ip += distance;
+                }
+                OpCode::JumpIfFalse => {
+                    let distance = frame.read_usize();
+                    if !self.peek().is_truthy() {
+                        frame.ip += distance;
+                    }
+                }
+                OpCode::Loop => {
+                    let distance = frame.read_usize();
+                    frame.ip -= distance;
+                }
+                OpCode::Call => {
+                    let count = frame.read_u8() as usize;
+                    self.call_value(&mut frame, count, offset)?;
+                }
+                OpCode::Closure => {
+                    let index = frame.read_usize();
+                    let function = &frame.chunk.functions[index];
+                    let code = Code::Bytecode {
+                        arity: function.arity,
+                        chunk: function.chunk.clone(),
+                    };
+                    let function = LoxFunction::new(
+                        function.name.clone(),
+                        code,
+                        self.environment.clone(),
+                        false,
+                    );
+                    self.stack.push(Value::Function(Rc::new(function)));
+                }
+                OpCode::Class => self.class(&mut frame, offset)?,
+                OpCode::EnterScope => self.environment.enter_scope(),
+                OpCode::ExitScope => self.environment.exit_scope(),
+                OpCode::Return => {
+                    let value = self.pop();
+                    self.environment = mem::take(&mut frame.caller_environment);
+                    let value = frame.instance.take().unwrap_or(value);
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            frame = caller;
+                            self.stack.push(value);
+                        }
+                        None => return Ok(()),
+                    }
+                }
+            }
+        }
+    }
+    fn undefined_global<T>(&self, index: usize, frame: &CallFrame, offset: usize) -> VmResult<T> {
+        Vm::error(
+            format!("Undefined variable '{}'.", self.globals.name(index)),
+            frame.chunk.span_at(offset),
+        )
+    }
+    fn undefined_local<T>(frame: &CallFrame, offset: usize) -> VmResult<T> {
+        Vm::error(
+            format!("Undefined variable '{}'.", frame.chunk.local_name(offset)),
+            frame.chunk.span_at(offset),
+        )
+    }
+    fn class(&mut self, frame: &mut CallFrame, offset: usize) -> VmResult<()> {
+        let name = frame.read_name();
+        let has_superclass = frame.read_u8() != 0;
+        let count = frame.read_u8();
+        let superclass = if has_superclass {
+            match self.pop() {
+                Value::Class(class) => Some(class),
+                _ => return Vm::error("Superclass must be a class.", frame.chunk.span_at(offset)),
+            }
+        } else {
+            None
+        };
+        let mut closure = self.environment.clone();
+        if let Some(superclass) = &superclass {
+            closure.enter_scope();
+            closure.define(Value::Class(superclass.clone()));
+        }
+        let methods = (0..count)
+            .map(|_| {
+                let index = frame.read_usize();
+                let function = &frame.chunk.functions[index];
+                let name = function.name.lexeme();
+                let code = Code::Bytecode {
+                    arity: function.arity,
+                    chunk: function.chunk.clone(),
+                };
+                let method =
+                    LoxFunction::new(function.name.clone(), code, closure.clone(), name == "init");
+                (name, Rc::new(method))
+            })
+            .collect::<HashMap<_, _>>();
+        let class = LoxClass::new(name.to_string(), superclass, methods);
+        self.stack.push(Value::Class(Rc::new(class)));
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn undefined_locals_are_named() {
+        let span = Span::new(6, 7, 1, 7);
+        let mut chunk = Chunk::default();
+        let offset = chunk.write_op(OpCode::GetLocal, span);
+        chunk.set_local_name(offset, "x");
+        chunk.write_u16(0);
+        chunk.write_u16(0);
+        chunk.write_op(OpCode::Return, span);
+        match Vm::new().interpret(chunk) {
+            Err(InterpreterError::RuntimeError(message, at)) => {
+                assert_eq!((message.as_str(), at), ("Undefined variable 'x'.", span))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -1 +1,88 @@
-use std::path::PathBuf;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `source` to a scratch file and returns its path.
+pub fn write_script(source: &str) -> PathBuf {
+    let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let path =
+        std::env::temp_dir().join(format!("lox_rs_ast_test_{}_{}.lox", std::process::id(), id));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+/// Creates an empty scratch directory and returns its path.
+pub fn scratch_dir() -> PathBuf {
+    let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let path = std::env::temp_dir().join(format!("lox_rs_ast_test_{}_{}", std::process::id(), id));
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+fn run_script(args: &[&str], path: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lox_rs_ast"))
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap()
+}
+
+fn assert_same(expected: &Output, actual: &Output, what: &str) {
+    assert_eq!(
+        String::from_utf8_lossy(&expected.stdout),
+        String::from_utf8_lossy(&actual.stdout),
+        "{} printed different output",
+        what
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&expected.stderr),
+        String::from_utf8_lossy(&actual.stderr),
+        "{} reported different errors",
+        what
+    );
+    assert_eq!(expected.status.code(), actual.status.code());
+}
+
+/// Runs `source` through the `lox_rs_ast` binary with `args` placed before
+/// the script path, once on each backend and once optimized, and checks
+/// that every run behaves identically.
+pub fn run_with(args: &[&str], source: &str) -> Output {
+    let path = write_script(source);
+    let tree = run_script(args, &path);
+    let vm = run_script(&[args, &["--backend", "vm"]].concat(), &path);
+    let optimized = run_script(&[args, &["-O"]].concat(), &path);
+    std::fs::remove_file(path).unwrap();
+    assert_same(&tree, &vm, "the VM");
+    assert_same(&tree, &optimized, "the optimized program");
+    tree
+}
+
+/// Runs `source` as a script and returns everything it printed to stdout.
+pub fn run(source: &str) -> String {
+    String::from_utf8(run_with(&["file"], source).stdout).unwrap()
+}
+
+/// Starts the REPL with `args`, types `input` into it and waits for it to
+/// exit. Its history is kept under `data_dir`.
+pub fn repl(args: &[&str], input: &str, data_dir: &Path) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox_rs_ast"))
+        .args(args)
+        .env("XDG_DATA_HOME", data_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
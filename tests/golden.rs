@@ -0,0 +1,36 @@
+//! Parser golden tests: every `tests/golden/*.lox` file is parsed and its
+//! S-expression dump compared with the `.sexp` file next to it. Run with
+//! `UPDATE_GOLDEN=1` to rewrite the expected output.
+use std::{fs, path::Path};
+
+use lox_rs_ast::{ast::AstPrinter, parser::Parser, scanner::Scanner};
+
+fn print(source: &str) -> String {
+    let (tokens, scan_errors) = Scanner::new(source).scan_tokens();
+    assert!(scan_errors.is_empty(), "{:?}", scan_errors);
+    let (statements, parse_errors) = Parser::new(tokens).parse();
+    assert!(parse_errors.is_empty(), "{:?}", parse_errors);
+    AstPrinter::default().print_program(&statements)
+}
+
+#[test]
+fn parser_output_matches_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut scripts: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty());
+    for script in scripts {
+        let actual = print(&fs::read_to_string(&script).unwrap());
+        let golden = script.with_extension("sexp");
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden).unwrap_or_default();
+        assert_eq!(actual, expected, "{} changed", script.display());
+    }
+}
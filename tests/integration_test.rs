@@ -1 +1,579 @@
+use std::path::PathBuf;
+
+use lox_rs_ast::{diagnostics::ColorChoice, interpreter::InterpreterError, LoxError, Options};
 mod common;
+
+#[test]
+fn sample_script() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sample.lox");
+    let source = std::fs::read_to_string(path).unwrap();
+    assert_eq!(common::run(&source), "11\nHello World\n326\n1232\n");
+}
+
+#[test]
+fn function_call_and_return() {
+    let source = r#"
+fun add(a, b) {
+    return a + b;
+}
+print add(1, 2);
+fun nothing() {}
+print nothing();
+print add;
+"#;
+    assert_eq!(common::run(source), "3\nnil\n<fn add>\n");
+}
+
+#[test]
+fn recursion() {
+    let source = r#"
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+print fib(10);
+"#;
+    assert_eq!(common::run(source), "55\n");
+}
+
+#[test]
+fn return_unwinds_nested_blocks() {
+    let source = r#"
+fun find() {
+    for (var i = 0; i < 10; i = i + 1) {
+        if (i == 3) {
+            return i;
+        }
+    }
+}
+var i = "global";
+print find();
+print i;
+"#;
+    assert_eq!(common::run(source), "3\nglobal\n");
+}
+
+#[test]
+fn closures_capture_their_environment() {
+    let source = r#"
+fun makeCounter() {
+    var i = 0;
+    fun count() {
+        i = i + 1;
+        return i;
+    }
+    return count;
+}
+var counter = makeCounter();
+print counter();
+print counter();
+var other = makeCounter();
+print other();
+"#;
+    assert_eq!(common::run(source), "1\n2\n1\n");
+}
+
+#[test]
+fn runaway_recursion_is_a_runtime_error() {
+    let output = common::run_with(
+        &["file", "--color", "never"],
+        "fun f(n) { return f(n + 1); }\nf(0);\n",
+    );
+    assert_eq!(output.status.code(), Some(70));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("error: Stack overflow."), "{}", stderr);
+}
+
+#[test]
+fn runaway_recursion_is_caught_on_a_default_thread_stack() {
+    // Test threads have a small stack, unlike the one the binary starts with.
+    let script = common::write_script("fun f(n) { return f(n + 1); }\nf(0);\n");
+    let options = Options {
+        color: ColorChoice::Never,
+        ..Options::default()
+    };
+    let result = lox_rs_ast::run_file(script.clone(), options);
+    std::fs::remove_file(script).unwrap();
+    match result {
+        Err(LoxError::InterpreterError(InterpreterError::RuntimeError(message, _))) => {
+            assert_eq!(message, "Stack overflow.")
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn arity_mismatch_is_a_runtime_error() {
+    let output = common::run_with(&["file"], "fun f(a) {}\nf(1, 2);\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Expected 1 arguments but got 2."));
+}
+
+#[test]
+fn calling_a_non_function_is_a_runtime_error() {
+    let output = common::run_with(&["file"], "\"not a function\"();\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Can only call functions and classes."));
+}
+
+#[test]
+fn class_fields_and_methods() {
+    let source = r#"
+class Counter {
+    init(start) {
+        this.count = start;
+    }
+    increment() {
+        this.count = this.count + 1;
+        return this;
+    }
+}
+var counter = Counter(5);
+counter.increment().increment();
+print counter.count;
+print counter;
+print Counter;
+var method = counter.increment;
+method();
+print counter.count;
+"#;
+    assert_eq!(common::run(source), "7\nCounter instance\nCounter\n8\n");
+}
+
+#[test]
+fn initializer_returns_this() {
+    let source = r#"
+class Foo {
+    init() {
+        this.x = 1;
+        return;
+    }
+}
+var foo = Foo();
+print foo.init() == foo;
+"#;
+    assert_eq!(common::run(source), "true\n");
+}
+
+#[test]
+fn inheritance_and_super() {
+    let source = r#"
+class Doughnut {
+    cook() {
+        print "Fry until golden brown.";
+    }
+    name() {
+        return "doughnut";
+    }
+}
+class BostonCream < Doughnut {
+    cook() {
+        super.cook();
+        print "Pipe full of custard and coat with chocolate.";
+    }
+}
+var d = BostonCream();
+d.cook();
+print d.name();
+"#;
+    assert_eq!(
+        common::run(source),
+        "Fry until golden brown.\nPipe full of custard and coat with chocolate.\ndoughnut\n"
+    );
+}
+
+#[test]
+fn undefined_property_is_a_runtime_error() {
+    let output = common::run_with(&["file"], "class A {}\nprint A().missing;\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Undefined property 'missing'."));
+}
+
+#[test]
+fn superclass_must_be_a_class() {
+    let output = common::run_with(&["file"], "var NotAClass = 1;\nclass A < NotAClass {}\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Superclass must be a class."));
+}
+
+#[test]
+fn runtime_errors_point_at_the_operand() {
+    let output = common::run_with(&["file"], "var a = 1;\nprint a +\n   nil * 2;\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("error: Left operand must be a number.\n --> "));
+    assert!(stderr.ends_with(".lox:3:4\n  |\n3 |    nil * 2;\n  |    ^^^\n"));
+}
+
+#[test]
+fn undefined_variable_reports_its_position() {
+    let output = common::run_with(
+        &["--color", "never", "file"],
+        "print 1;\n  print missing;\n",
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error: Undefined variable 'missing'."));
+    assert!(stderr.contains(".lox:2:9\n  |\n2 |   print missing;\n  |         ^^^^^^^\n"));
+}
+
+#[test]
+fn parse_errors_are_rendered_with_a_snippet() {
+    let output = common::run_with(&["file"], "print (1 + );\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("error: Expected expression.\n"));
+    assert!(stderr.contains("1 | print (1 + );\n  |            ^\n"));
+}
+
+#[test]
+fn all_syntax_errors_are_reported() {
+    let output = common::run_with(
+        &["file"],
+        "print 1 +;\nprint 2;\nvar = 3;\n{\n  print ;\n}\n",
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("error: ").count(), 3);
+    assert!(stderr.contains(":1:10\n"));
+    assert!(stderr.contains(":3:5\n"));
+    assert!(stderr.contains(":5:9\n"));
+    assert_eq!(output.stdout, b"");
+}
+
+#[test]
+fn lexical_and_syntax_errors_are_reported_together() {
+    let output = common::run_with(&["file"], "print 1 @ 2;\nvar = 3;\n\"open;\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let positions: Vec<_> = stderr
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix("--> "))
+        .map(|location| location.rsplitn(3, ':').take(2).collect::<Vec<_>>())
+        .collect();
+    assert_eq!(
+        positions,
+        vec![vec!["9", "1"], vec!["5", "2"], vec!["1", "3"]]
+    );
+}
+
+#[test]
+fn string_escapes_and_raw_strings() {
+    let output = common::run(r##"print "a\tb\n\"c\" \u{e9}"; print r#"\d+ "raw""#;"##);
+    assert_eq!(output, "a\tb\n\"c\" é\n\\d+ \"raw\"\n");
+    // A backslash starts an escape in every dialect, so it must be doubled.
+    assert_eq!(common::run(r#"print "C:\\path";"#), "C:\\path\n");
+    let output = common::run_with(&["--color", "never", "file"], r#"print "C:\path";"#);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("error: Invalid escape sequence '\\p'.\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn closures_bind_to_the_variable_in_scope_at_declaration() {
+    let output = common::run(
+        r#"
+var a = "global";
+{
+  fun show() { print a; }
+  show();
+  var a = "block";
+  show();
+}
+"#,
+    );
+    assert_eq!(output, "global\nglobal\n");
+}
+
+#[test]
+fn static_errors_stop_the_program_before_it_runs() {
+    let output = common::run_with(
+        &["--color", "never", "file"],
+        "print \"side effect\";\n{ var a = 1; var a = 2; var b = b; }\nreturn;\n",
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.stdout, b"");
+    assert!(stderr.contains("error: Already a variable named 'a' in this scope."));
+    assert!(stderr.contains("error: Can't read local variable in its own initializer."));
+    assert!(stderr.contains("error: Can't return from top-level code."));
+    assert!(stderr.contains(":3:1\n"));
+}
+
+#[test]
+fn optimizing_keeps_runtime_errors() {
+    let source = "print 1 + 2 * 3;\nif (1 > 2) print \"dead\"; else print (\"a\" + \"b\");\nprint (\"a\") - 1;\n";
+    let output = common::run_with(&["file"], source);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "7\nab\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Left operand must be a number."));
+    assert!(stderr.ends_with("3 | print (\"a\") - 1;\n  |       ^^^^^\n"));
+}
+
+#[test]
+fn tokens_are_dumped_as_json() {
+    let output = common::run_with(&["tokens", "--format", "json"], "print \"hi\";\n");
+    let tokens: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let kinds: Vec<_> = tokens
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|token| token["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["Print", "String", "Semicolon", "Eof"]);
+    assert_eq!(tokens[1]["value"], "hi");
+    assert_eq!(
+        tokens[1]["span"],
+        serde_json::json!({ "start": 6, "end": 10, "line": 1, "column": 7 })
+    );
+}
+
+#[test]
+fn ast_is_dumped_as_a_tree() {
+    let output = common::run_with(
+        &["ast"],
+        "var a = -(1 + b);\nif (a) print a; else print nil;\n",
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Var name=a @1:1\n  \
+           initializer: Unary operator=- @1:9\n    \
+             right: Grouping @1:10\n      \
+               expression: Binary operator=+ @1:11\n        \
+                 left: Literal value=1.0 @1:11\n        \
+                 right: Variable name=b @1:15\n\
+         If @2:1\n  \
+           condition: Variable name=a @2:5\n  \
+           then_branch: Print @2:8\n    \
+             expression: Variable name=a @2:14\n  \
+           else_branch: Print @2:22\n    \
+             expression: Literal value=null @2:28\n"
+    );
+}
+
+#[test]
+fn ast_dump_reports_syntax_errors() {
+    let output = common::run_with(&["ast", "--color", "never"], "print 1 +;\n");
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("error: Expected expression."));
+}
+
+#[test]
+fn fmt_check_reports_unformatted_files() {
+    let output = common::run_with(&["fmt", "--check"], "print  1;\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("is not formatted."));
+    let output = common::run_with(&["fmt", "--check"], "print 1;\n");
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn fmt_write_rewrites_files_in_place() {
+    let path = common::write_script("var a=1;{print a;}// done\n");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_lox_rs_ast"))
+        .args(["fmt", "--write"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let formatted = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(formatted, "var a = 1;\n{\n  print a;\n} // done\n");
+}
+
+#[test]
+fn check_reports_lints_with_their_codes() {
+    let source = "fun f() {\n  var x = 1;\n  return 2;\n  print x;\n}\n";
+    let output = common::run_with(&["check", "--color", "never"], source);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("warning[L004]: Unreachable code.\n"));
+    assert!(stderr.contains("\n4 |   print x;\n"));
+    let output = common::run_with(&["check", "--allow", "L004"], source);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn clock_is_a_native_function() {
+    let source = "var start = clock();\nprint clock() >= start;\nprint clock;\nfun f() { return clock; }\nprint f() == clock;\nclock(1);\n";
+    let output = common::run_with(&["file", "--color", "never"], source);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "true\n<native fn clock>\ntrue\n"
+    );
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("error: Expected 0 arguments but got 1.\n --> "));
+}
+
+#[test]
+fn repl_survives_errors_and_reads_multi_line_input() {
+    let data_dir = common::scratch_dir();
+    let output = common::repl(
+        &["--color", "never"],
+        "var a = 1;\nprint );\nprint b;\nfun f() {\n  return a\n    + 1;\n}\nprint f();\n",
+        &data_dir,
+    );
+    std::fs::remove_dir_all(data_dir).unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("error: Expected expression."));
+    assert!(stderr.contains("error: Undefined variable 'b'."));
+}
+
+#[test]
+fn repl_echoes_expressions_and_runs_commands() {
+    let script = common::write_script("var loaded = 2;\n");
+    let input = format!(
+        "1 + 2;\nvar a = 1;\na = 5;\n:load {}\n:env\n:reset\n:env\n:quit\nprint 9;\n",
+        script.display()
+    );
+    let data_dir = common::scratch_dir();
+    let output = common::repl(&[], &input, &data_dir);
+    std::fs::remove_file(script).unwrap();
+    std::fs::remove_dir_all(data_dir).unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "3\nclock = <native fn clock>\na = 5\nloaded = 2\nclock = <native fn clock>\n"
+    );
+}
+
+#[test]
+fn repl_echoes_calls_only_when_they_return_something() {
+    let data_dir = common::scratch_dir();
+    for backend in ["tree", "vm"] {
+        let output = common::repl(
+            &["--backend", backend],
+            "fun f() {}\nfun g() { return 2; }\nf();\ng();\nnil;\n",
+            &data_dir,
+        );
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\nnil\n");
+    }
+    std::fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[test]
+fn repl_reports_unfinished_input_at_the_end() {
+    let data_dir = common::scratch_dir();
+    let output = common::repl(&["--color", "never"], "print 1;\nprint 2", &data_dir);
+    std::fs::remove_dir_all(data_dir).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("error: Expect ';' after value."),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn repl_errors_point_into_earlier_inputs() {
+    let data_dir = common::scratch_dir();
+    for backend in ["tree", "vm"] {
+        let output = common::repl(
+            &["--color", "never", "--backend", backend],
+            "fun f() {\n  return nil + 1;\n}\nprint 1;\nf();\n",
+            &data_dir,
+        );
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains(" --> <repl>:2:14\n  |\n2 |   return nil + 1;\n  |              ^\n"),
+            "{}",
+            stderr
+        );
+    }
+    std::fs::remove_dir_all(data_dir).unwrap();
+}
+
+#[test]
+fn repl_history_persists_across_sessions() {
+    let data_dir = common::scratch_dir();
+    common::repl(&[], "var a = 1;\n:env\n", &data_dir);
+    common::repl(&[], "print 2;\n", &data_dir);
+    let history = std::fs::read_to_string(data_dir.join("lox_rs_ast").join("history.txt")).unwrap();
+    std::fs::remove_dir_all(data_dir).unwrap();
+    let entries: Vec<_> = history
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    assert_eq!(entries, ["var a = 1;", ":env", "print 2;"]);
+}
+
+#[test]
+fn interactive_run_keeps_the_script_globals() {
+    let script =
+        common::write_script("var count = 2;\nfun twice(n) { return n * 2; }\nprint \"loaded\";\n");
+    let data_dir = common::scratch_dir();
+    let script_path = script.display().to_string();
+    let output = common::repl(
+        &["run", "--interactive", &script_path],
+        "twice(count);\ncount = 5;\ncount;\n",
+        &data_dir,
+    );
+    std::fs::remove_file(script).unwrap();
+    std::fs::remove_dir_all(data_dir).unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "loaded\n4\n5\n");
+}
+
+#[test]
+fn interactive_run_errors_point_into_the_script() {
+    let script = common::write_script("var count = 2;\nfun broken() {\n  return count + nil;\n}\n");
+    let data_dir = common::scratch_dir();
+    let script_path = script.display().to_string();
+    let output = common::repl(
+        &["--color", "never", "run", "--interactive", &script_path],
+        "print 1;\nbroken();\n",
+        &data_dir,
+    );
+    std::fs::remove_file(script).unwrap();
+    std::fs::remove_dir_all(data_dir).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let expected = format!(
+        " --> {}:3:16\n  |\n3 |   return count + nil;\n  |                ^\n",
+        script_path
+    );
+    assert!(stderr.contains(&expected), "{}", stderr);
+}
+
+#[test]
+fn failures_exit_with_sysexits_codes() {
+    for (source, code, message) in [
+        ("print @;\n", 65, "error: Unexpected character '@'.\n"),
+        ("print (1;\n", 65, "error: Expect ')' after expression.\n"),
+        (
+            "return 1;\n",
+            65,
+            "error: Can't return from top-level code.\n",
+        ),
+        (
+            "print 1;\nprint -\"a\";\nprint 2;\n",
+            70,
+            "error: Operand must be a number.\n",
+        ),
+    ] {
+        let output = common::run_with(&["--color", "never", "file"], source);
+        assert_eq!(output.status.code(), Some(code), "{:?}", source);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with(message), "{:?}", stderr);
+        assert!(!stderr.contains("Error:"), "{:?}", stderr);
+    }
+}
+
+#[test]
+fn unreadable_files_exit_with_74() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_lox_rs_ast"))
+        .args(["--color", "never", "file", "does/not/exist.lox"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(74));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("error: Can't read does/not/exist.lox: "));
+}